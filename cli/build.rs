@@ -0,0 +1,68 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::path::Path;
+use std::process::Command;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+// Embed build information shown by `dyno version` and `dyno self version`.
+
+fn git_commit() -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn main() {
+    println!(
+        "cargo:rustc-env=DYNO_GIT_COMMIT={}",
+        git_commit().unwrap_or_else(|| "unknown".to_string())
+    );
+
+    // Honor reproducible build timestamps, see https://reproducible-builds.org
+    let build_timestamp = std::env::var("SOURCE_DATE_EPOCH").unwrap_or_else(|_| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0)
+            .to_string()
+    });
+    println!("cargo:rustc-env=DYNO_BUILD_TIMESTAMP={}", build_timestamp);
+
+    // Cargo passes the enabled features as CARGO_FEATURE_<NAME>
+    let mut features: Vec<String> = std::env::vars()
+        .filter_map(|(key, _)| {
+            key.strip_prefix("CARGO_FEATURE_")
+                .map(|name| name.to_lowercase().replace('_', "-"))
+        })
+        .filter(|name| name != "default")
+        .collect();
+    features.sort();
+    println!("cargo:rustc-env=DYNO_FEATURES={}", features.join(","));
+
+    // The dynolog release whose rpcs this dyno speaks
+    let dynolog_version = std::fs::read_to_string("../version.txt").unwrap_or_default();
+    println!(
+        "cargo:rustc-env=DYNOLOG_SOURCE_VERSION={}",
+        dynolog_version.trim()
+    );
+    println!("cargo:rerun-if-changed=../version.txt");
+
+    // The reflog changes with every commit and checkout. Source tarballs have
+    // no .git, and a missing path would rerun this script on every build.
+    let reflog = Path::new("../.git/logs/HEAD");
+    if reflog.exists() {
+        println!("cargo:rerun-if-changed={}", reflog.display());
+    }
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+}
@@ -0,0 +1,167 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+#[cfg(unix)]
+use std::sync::OnceLock;
+
+use anyhow::Context;
+use anyhow::Result;
+
+// This module contains the writer for files dyno creates, e.g. --out and
+// merged traces. Files are written under a temporary name and renamed into
+// place once complete, so an interrupted run never leaves a truncated file
+// that looks valid.
+
+static FSYNC: AtomicBool = AtomicBool::new(false);
+
+/// Temporary file to remove when the process is interrupted
+#[cfg(unix)]
+static INTERRUPT_TMP_PATH: OnceLock<std::ffi::CString> = OnceLock::new();
+
+/// Remove the temporary file and die of `signal` as if it wasn't handled.
+/// Only async-signal-safe calls are allowed here.
+#[cfg(unix)]
+extern "C" fn on_interrupt(signal: libc::c_int) {
+    if let Some(path) = INTERRUPT_TMP_PATH.get() {
+        unsafe { libc::unlink(path.as_ptr()) };
+    }
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::raise(signal);
+    }
+}
+
+/// Flush files to disk before renaming them into place, see --fsync
+pub fn init(fsync: bool) {
+    FSYNC.store(fsync, Ordering::Relaxed);
+}
+
+/// A file that only appears at its path once `commit` is called. Dropping it
+/// before removes the partial temporary file.
+pub struct AtomicFile {
+    path: PathBuf,
+    tmp_path: PathBuf,
+    writer: Option<BufWriter<File>>,
+}
+
+/// ".trace.json.tmp1234" next to "trace.json"
+fn tmp_path(path: &Path) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!(".{}.tmp{}", name, std::process::id()))
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> Result<Self> {
+        let tmp_path = tmp_path(path);
+        let file = File::create(&tmp_path)
+            .with_context(|| format!("Unable to create {}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            tmp_path,
+            writer: Some(BufWriter::new(file)),
+        })
+    }
+
+    /// Remove the temporary file if the process is killed by SIGINT, SIGTERM
+    /// or SIGHUP before `commit`. Only the first file this is called for is
+    /// removed, which is the --out file.
+    pub fn remove_on_interrupt(&self) {
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStrExt;
+
+            let Ok(path) = std::ffi::CString::new(self.tmp_path.as_os_str().as_bytes()) else {
+                return;
+            };
+            if INTERRUPT_TMP_PATH.set(path).is_ok() {
+                let handler = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+                for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGHUP] {
+                    unsafe { libc::signal(signal, handler) };
+                }
+            }
+        }
+    }
+
+    /// Move the complete file into place
+    pub fn commit(mut self) -> Result<()> {
+        let writer = self.writer.take().unwrap();
+        let file = writer.into_inner().map_err(|err| err.into_error())?;
+        let fsync = FSYNC.load(Ordering::Relaxed);
+        if fsync {
+            file.sync_all()?;
+        }
+        drop(file);
+        fs::rename(&self.tmp_path, &self.path)
+            .with_context(|| format!("Unable to write {}", self.path.display()))?;
+        // The rename itself is only durable once the directory is synced
+        #[cfg(unix)]
+        if fsync {
+            let dir = match self.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            File::open(dir)?.sync_all()?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.as_mut().unwrap().flush()
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        if self.writer.take().is_some() {
+            let _ = fs::remove_file(&self.tmp_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atomic_file() {
+        let dir = std::env::temp_dir().join(format!("dyno_atomic_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.json");
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"partial").unwrap();
+        assert!(!path.exists());
+        drop(file);
+        assert!(!path.exists());
+        assert!(!tmp_path(&path).exists());
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"{}").unwrap();
+        file.commit().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "{}");
+        assert!(!tmp_path(&path).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
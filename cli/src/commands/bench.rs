@@ -0,0 +1,154 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Result;
+use serde_json::json;
+
+use super::progress;
+use super::render;
+use super::render::OutputFormat;
+use super::utils::DynoClient;
+
+// This module contains the handling logic for dyno bench, which measures rpc
+// throughput and latency of a dynolog daemon, e.g. to validate protocol or
+// server changes under load.
+
+/// Read-only rpcs that are safe to send in bulk
+#[derive(Clone, Copy, Debug, clap::ValueEnum)]
+pub enum BenchRpc {
+    GetVersion,
+    GetStatus,
+}
+
+impl BenchRpc {
+    fn request(self) -> &'static str {
+        match self {
+            BenchRpc::GetVersion => r#"{"fn":"getVersion"}"#,
+            BenchRpc::GetStatus => r#"{"fn":"getStatus"}"#,
+        }
+    }
+}
+
+/// Latency at percentile `pct` of sorted `latencies`, nearest rank
+fn percentile(latencies: &[Duration], pct: f64) -> Duration {
+    if latencies.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = ((pct / 100.0) * latencies.len() as f64).ceil() as usize;
+    latencies[rank.clamp(1, latencies.len()) - 1]
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+/// Send `requests` rpcs to dynolog from `concurrency` parallel connections
+pub fn run_bench(
+    client: &DynoClient,
+    requests: usize,
+    concurrency: usize,
+    rpc: BenchRpc,
+    format: OutputFormat,
+) -> Result<()> {
+    // Every rpc would start its own spinner
+    progress::init(false);
+    let next = AtomicUsize::new(0);
+    let latencies = Mutex::new(Vec::with_capacity(requests));
+    let errors = AtomicUsize::new(0);
+    let first_error = Mutex::new(None);
+
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..concurrency.min(requests) {
+            scope.spawn(|| {
+                while next.fetch_add(1, Ordering::Relaxed) < requests {
+                    let sent = Instant::now();
+                    match client.rpc(rpc.request()) {
+                        Ok(_) => latencies.lock().unwrap().push(sent.elapsed()),
+                        Err(err) => {
+                            errors.fetch_add(1, Ordering::Relaxed);
+                            first_error
+                                .lock()
+                                .unwrap()
+                                .get_or_insert_with(|| format!("{:#}", err));
+                        }
+                    }
+                }
+            });
+        }
+    });
+    let elapsed = start.elapsed();
+
+    let mut latencies = latencies.into_inner().unwrap();
+    latencies.sort();
+    let errors = errors.into_inner();
+    if let Some(err) = first_error.into_inner().unwrap() {
+        tracing::warn!("{} requests failed, first error: {}", errors, err);
+    }
+    if latencies.is_empty() {
+        return Err(anyhow::anyhow!("All {} requests failed", requests));
+    }
+    let throughput = latencies.len() as f64 / elapsed.as_secs_f64();
+
+    if format.is_structured() {
+        return render::print(
+            format,
+            &json!({
+                "requests": requests,
+                "concurrency": concurrency,
+                "errors": errors,
+                "elapsed_ms": ms(elapsed),
+                "requests_per_s": throughput,
+                "latency_ms": {
+                    "p50": ms(percentile(&latencies, 50.0)),
+                    "p90": ms(percentile(&latencies, 90.0)),
+                    "p99": ms(percentile(&latencies, 99.0)),
+                    "max": ms(percentile(&latencies, 100.0)),
+                },
+            }),
+        );
+    }
+
+    println!(
+        "{} requests, {} errors, concurrency {}, in {:.3} s",
+        requests,
+        errors,
+        concurrency,
+        elapsed.as_secs_f64()
+    );
+    println!("Throughput  {:.1} requests/s", throughput);
+    println!(
+        "Latency     p50 {:.3} ms, p90 {:.3} ms, p99 {:.3} ms, max {:.3} ms",
+        ms(percentile(&latencies, 50.0)),
+        ms(percentile(&latencies, 90.0)),
+        ms(percentile(&latencies, 99.0)),
+        ms(percentile(&latencies, 100.0))
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let latencies: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&latencies, 50.0), Duration::from_millis(50));
+        assert_eq!(percentile(&latencies, 99.0), Duration::from_millis(99));
+        assert_eq!(percentile(&latencies, 100.0), Duration::from_millis(100));
+        assert_eq!(percentile(&latencies[..1], 90.0), Duration::from_millis(1));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+}
@@ -0,0 +1,110 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::io;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Result;
+
+use super::progress::Progress;
+use super::prompt;
+use super::utils::DynoClient;
+
+// This module contains --bootstrap-ssh, which starts dynolog over ssh when
+// it is not running on the target host.
+
+pub const DEFAULT_BOOTSTRAP_CMD: &str =
+    "systemctl is-active --quiet dynolog || sudo systemctl start dynolog";
+
+/// How long to wait for dynolog to accept connections once started
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub fn is_connection_refused(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<io::Error>())
+        .any(|err| err.kind() == io::ErrorKind::ConnectionRefused)
+}
+
+/// Run `bootstrap_cmd` on the dynolog host over ssh if nothing listens on
+/// the dynolog port, then wait until dynolog accepts connections.
+pub fn ensure_running(client: &DynoClient, bootstrap_cmd: &str, assume_yes: bool) -> Result<()> {
+    let err = match client.connect() {
+        Ok(_) => return Ok(()),
+        Err(err) if is_connection_refused(&err) => err,
+        Err(err) => return Err(err),
+    };
+    tracing::info!("{:#}", err);
+    prompt::confirm(
+        &format!(
+            "start dynolog on {} over ssh with `{}`",
+            client.hostname, bootstrap_cmd
+        ),
+        assume_yes,
+    )?;
+
+    tracing::info!(
+        "Running `{}` on {} over ssh",
+        bootstrap_cmd,
+        client.hostname
+    );
+    let status = Command::new("ssh")
+        .arg(&client.hostname)
+        .arg(bootstrap_cmd)
+        .status()
+        .context("Couldn't run ssh, is it installed and in PATH?")?;
+    if !status.success() {
+        return Err(anyhow::anyhow!(
+            "Starting dynolog on {} over ssh failed ({}), see the output above",
+            client.hostname,
+            status
+        ));
+    }
+
+    let _progress = Progress::start(&format!(
+        "Waiting for dynolog to start on {}:{}",
+        client.hostname, client.port
+    ));
+    let start = Instant::now();
+    loop {
+        match client.connect() {
+            Ok(_) => return Ok(()),
+            Err(err) if start.elapsed() >= STARTUP_TIMEOUT => {
+                return Err(err.context(format!(
+                    "dynolog didn't start listening within {}",
+                    humantime::format_duration(STARTUP_TIMEOUT)
+                )));
+            }
+            Err(_) => thread::sleep(POLL_INTERVAL),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    #[test]
+    fn test_is_connection_refused() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let err = DynoClient::new("127.0.0.1", port, None)
+            .connect()
+            .unwrap_err();
+        assert!(is_connection_refused(&err));
+        assert!(!is_connection_refused(&anyhow::anyhow!("timed out")));
+    }
+}
@@ -0,0 +1,210 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+
+use super::gputrace::GpuTracePreset;
+use super::render::OutputFormat;
+
+// This module contains the user config file that provides defaults for
+// command line flags. Flags that are set explicitly always take precedence.
+
+/// User defaults, read from ~/.config/dyno/config.toml or --config
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub hostname: Option<String>,
+    pub port: Option<u16>,
+    /// Ports to try when dynolog refuses connections on the default port,
+    /// the one that answered is remembered per host, see commands/discovery.rs
+    pub alternate_ports: Vec<u16>,
+    /// Duration such as "10s", see --rpc-timeout
+    pub rpc_timeout: Option<String>,
+    pub format: Option<OutputFormat>,
+    /// Operator name sent with every request, see --as
+    pub user: Option<String>,
+    pub gputrace: GpuTraceDefaults,
+    /// Named connection settings, selected with --profile
+    pub profiles: BTreeMap<String, ConnectionProfile>,
+    /// Shorthands for a subcommand and its flags, e.g.
+    /// trace5s = "gputrace --duration-ms 5000 --record-shapes"
+    pub aliases: BTreeMap<String, String>,
+    /// Extra keys to redact from verbose logs, matched case insensitively as
+    /// substrings of json keys and KEY=VALUE kineto config lines
+    pub redact_keys: Vec<String>,
+    /// File the config was loaded from
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+}
+
+/// Connection settings of one cluster or environment
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConnectionProfile {
+    pub hostname: Option<String>,
+    pub port: Option<u16>,
+    pub rpc_timeout: Option<String>,
+}
+
+impl Config {
+    /// Use the connection settings of a named profile over the top level ones,
+    /// returns the profile so they can be ranked above the environment
+    pub fn select_profile(&mut self, name: &str) -> Result<ConnectionProfile> {
+        let profile = self.profiles.remove(name).ok_or_else(|| {
+            anyhow::anyhow!(
+                "Unknown profile '{}', the config file defines: [{}]",
+                name,
+                self.profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+            )
+        })?;
+        self.hostname = profile.hostname.clone().or(self.hostname.take());
+        self.port = profile.port.or(self.port);
+        self.rpc_timeout = profile.rpc_timeout.clone().or(self.rpc_timeout.take());
+        Ok(profile)
+    }
+
+    /// Where discovered ports are remembered, next to the config file
+    pub fn port_cache_path(&self) -> Option<PathBuf> {
+        let config_path = self.path.clone().or_else(default_path)?;
+        Some(config_path.with_file_name("ports.json"))
+    }
+
+    /// Split the expansion of alias `name` into arguments, using shell quoting
+    pub fn alias_args(&self, name: &str) -> Option<Result<Vec<String>>> {
+        self.aliases.get(name).map(|expansion| {
+            shlex::split(expansion)
+                .ok_or_else(|| anyhow::anyhow!("Invalid quoting in alias '{}'", name))
+        })
+    }
+}
+
+/// Defaults for dyno gputrace flags
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GpuTraceDefaults {
+    pub duration_ms: Option<u64>,
+    pub process_limit: Option<u32>,
+    pub preset: Option<GpuTracePreset>,
+    pub record_shapes: Option<bool>,
+    pub profile_memory: Option<bool>,
+    pub with_stacks: Option<bool>,
+    pub with_flops: Option<bool>,
+    pub with_modules: Option<bool>,
+}
+
+/// $XDG_CONFIG_HOME/dyno/config.toml, falling back to ~/.config, and to
+/// %APPDATA% on Windows where HOME is usually not set
+fn default_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(config_dir.join("dyno").join("config.toml"))
+}
+
+fn parse(content: &str) -> Result<Config> {
+    toml::from_str(content).map_err(|err| err.into())
+}
+
+/// Load the config file at `path`, or the default one if it exists
+pub fn load(path: Option<&Path>) -> Result<Config> {
+    let path = match path {
+        Some(path) => path.to_path_buf(),
+        None => match default_path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Config::default()),
+        },
+    };
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Unable to read config file {}", path.display()))?;
+    let mut config =
+        parse(&content).with_context(|| format!("Invalid config file {}", path.display()))?;
+    config.path = Some(path);
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let config = parse(
+            r#"
+hostname = "trainer-01"
+format = "json"
+
+[gputrace]
+duration_ms = 2000
+preset = "memory-debug"
+with_flops = true
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.hostname.as_deref(), Some("trainer-01"));
+        assert_eq!(config.port, None);
+        assert_eq!(config.format, Some(OutputFormat::Json));
+        assert_eq!(config.gputrace.duration_ms, Some(2000));
+        assert_eq!(config.gputrace.preset, Some(GpuTracePreset::MemoryDebug));
+        assert_eq!(config.gputrace.with_flops, Some(true));
+
+        // Typos should not be silently ignored
+        assert!(parse("hostnme = \"trainer-01\"").is_err());
+    }
+
+    #[test]
+    fn test_select_profile() {
+        let content = r#"
+hostname = "localhost"
+rpc_timeout = "10s"
+
+[profiles.prod-eu]
+hostname = "trainer-eu.example.com"
+port = 1779
+"#;
+        let mut config = parse(content).unwrap();
+        config.select_profile("prod-eu").unwrap();
+        assert_eq!(config.hostname.as_deref(), Some("trainer-eu.example.com"));
+        assert_eq!(config.port, Some(1779));
+        assert_eq!(config.rpc_timeout.as_deref(), Some("10s"));
+
+        let mut config = parse(content).unwrap();
+        let err = config.select_profile("prod-us").unwrap_err();
+        assert!(err.to_string().contains("[prod-eu]"), "{}", err);
+    }
+
+    #[test]
+    fn test_alias_args() {
+        let config = parse(
+            r#"
+[aliases]
+trace5s = "gputrace --duration-ms 5000 --log-file '/tmp/my trace.json'"
+broken = "gputrace --log-file '/tmp"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.alias_args("trace5s").unwrap().unwrap(),
+            vec![
+                "gputrace",
+                "--duration-ms",
+                "5000",
+                "--log-file",
+                "/tmp/my trace.json"
+            ]
+        );
+        assert!(config.alias_args("broken").unwrap().is_err());
+        assert!(config.alias_args("status").is_none());
+    }
+}
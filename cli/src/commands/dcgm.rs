@@ -5,17 +5,81 @@
  * LICENSE file in the root directory of this source tree.
  */
 
-use std::net::TcpStream;
-
 use anyhow::Result;
+use chrono::Duration;
+use chrono::Local;
+use chrono::NaiveDateTime;
+use chrono::NaiveTime;
+use chrono::Timelike;
+use chrono::Utc;
+use serde_json::json;
+use serde_json::Value;
 
-#[path = "utils.rs"]
-mod utils;
+use super::prompt;
+use super::render;
+use super::render::OutputFormat;
+use super::style;
+use super::timestamp;
+use super::utils::DynoClient;
 
 // This module contains the handling logic for dcgm
 
+/// Parse a local wall clock time such as "16:30" or "16:30:15"
+pub fn parse_wall_clock(s: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(s, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M:%S"))
+        .map_err(|_| anyhow::anyhow!("Expected a time of day as HH:MM or HH:MM:SS, got '{}'", s))
+}
+
+/// Seconds from `now` until the next occurrence of `until`, today or tomorrow.
+fn seconds_until(until: NaiveTime, now: NaiveDateTime) -> i64 {
+    let mut target = now.date().and_time(until);
+    if target <= now {
+        target += Duration::days(1);
+    }
+    (target - now).num_seconds()
+}
+
+/// Resolve the pause duration from either --duration-s, --duration or --until
+pub fn pause_duration_s(
+    duration_s: i32,
+    duration: Option<std::time::Duration>,
+    until: Option<NaiveTime>,
+) -> Result<i32> {
+    if let Some(duration) = duration {
+        if duration.subsec_nanos() != 0 {
+            return Err(anyhow::anyhow!(
+                "dcgm profiling can only be paused for whole seconds"
+            ));
+        }
+        return i32::try_from(duration.as_secs()).map_err(|err| err.into());
+    }
+    match until {
+        Some(until) => {
+            let now = Local::now().naive_local();
+            let now = now.with_nanosecond(0).unwrap_or(now);
+            let seconds = seconds_until(until, now);
+            i32::try_from(seconds).map_err(|err| err.into())
+        }
+        None => Ok(duration_s),
+    }
+}
+
 /// Pause dcgm module profiling
-pub fn run_dcgm_pause(client: TcpStream, duration_s: i32) -> Result<()> {
+pub fn run_dcgm_pause(
+    client: &DynoClient,
+    duration_s: i32,
+    yes: bool,
+    format: OutputFormat,
+) -> Result<()> {
+    prompt::confirm(
+        &format!(
+            "pause dcgm profiling on {}:{} for {}s",
+            client.hostname, client.port, duration_s
+        ),
+        yes,
+    )?;
+
     let request_json = format!(
         r#"
 {{
@@ -25,23 +89,69 @@ pub fn run_dcgm_pause(client: TcpStream, duration_s: i32) -> Result<()> {
         duration_s
     );
 
-    utils::send_msg(&client, &request_json).expect("Error sending message to service");
+    // Fails with a DaemonError if dynolog couldn't pause profiling
+    let resp_str = client.rpc(&request_json)?;
 
-    let resp_str = utils::get_resp(&client).expect("Unable to decode output bytes");
+    let resume_time = timestamp::format(Utc::now() + Duration::seconds(duration_s.into()));
 
-    println!("response = {}", resp_str);
+    if format.is_structured() {
+        return render::print(
+            format,
+            &json!({
+                "response": serde_json::from_str::<Value>(&resp_str)?,
+                "duration_s": duration_s,
+                "resume_time": resume_time,
+            }),
+        );
+    }
+
+    println!(
+        "{}",
+        style::success(&format!(
+            "DCGM profiling paused for {}s, it will resume at {}",
+            duration_s, resume_time
+        ))
+    );
 
     Ok(())
 }
 
 /// Resume dcgm module profiling
-pub fn run_dcgm_resume(client: TcpStream) -> Result<()> {
-    utils::send_msg(&client, r#"{"fn":"dcgmProfResume"}"#)
-        .expect("Error sending message to service");
+pub fn run_dcgm_resume(client: &DynoClient, format: OutputFormat) -> Result<()> {
+    let resp_str = client.rpc(r#"{"fn":"dcgmProfResume"}"#)?;
 
-    let resp_str = utils::get_resp(&client).expect("Unable to decode output bytes");
-
-    println!("response = {}", resp_str);
+    if format.is_structured() {
+        return render::print(format, &serde_json::from_str(&resp_str)?);
+    }
+    println!("{}", style::success("DCGM profiling resumed"));
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pause_until() {
+        let now = NaiveTime::from_hms_opt(16, 0, 0).unwrap();
+        let now = chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+            .unwrap()
+            .and_time(now);
+        assert_eq!(seconds_until(parse_wall_clock("16:30").unwrap(), now), 1800);
+        assert_eq!(
+            seconds_until(parse_wall_clock("15:59:30").unwrap(), now),
+            24 * 3600 - 30
+        );
+        assert!(parse_wall_clock("4pm").is_err());
+    }
+
+    #[test]
+    fn test_pause_duration() {
+        let duration = crate::commands::utils::parse_duration("2m").unwrap();
+        assert_eq!(pause_duration_s(300, Some(duration), None).unwrap(), 120);
+        let duration = crate::commands::utils::parse_duration("1500ms").unwrap();
+        assert!(pause_duration_s(300, Some(duration), None).is_err());
+        assert_eq!(pause_duration_s(300, None, None).unwrap(), 300);
+    }
+}
@@ -0,0 +1,147 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::sync::Mutex;
+use std::thread;
+use std::thread::ThreadId;
+use std::time::Duration;
+
+// This module contains --deadline, a hard limit on the whole invocation:
+// name resolution, connecting, every rpc, retries and waits. A watchdog
+// thread ends the process when it expires, since blocking calls such as DNS
+// lookups can't be interrupted, and prints which rpcs had completed.
+
+/// Exit code when --deadline expired
+pub const EXIT_DEADLINE: i32 = 4;
+
+type Cleanup = Box<dyn FnOnce() + Send>;
+
+/// Rpcs that completed, with their outcome, and the ones in flight. Commands
+/// such as bench send rpcs from several threads, so each thread has at most
+/// one rpc in flight.
+struct Rpcs {
+    completed: Vec<String>,
+    in_flight: Vec<(ThreadId, String)>,
+}
+
+impl Rpcs {
+    const fn new() -> Self {
+        Rpcs {
+            completed: Vec::new(),
+            in_flight: Vec::new(),
+        }
+    }
+
+    fn start(&mut self, thread: ThreadId, name: &str) {
+        self.in_flight.retain(|(id, _)| *id != thread);
+        self.in_flight.push((thread, name.to_string()));
+    }
+
+    fn finish(&mut self, thread: ThreadId, ok: bool) {
+        if let Some(pos) = self.in_flight.iter().position(|(id, _)| *id == thread) {
+            let (_, name) = self.in_flight.remove(pos);
+            self.completed
+                .push(format!("{} {}", name, if ok { "ok" } else { "failed" }));
+        }
+    }
+}
+
+static RPCS: Mutex<Rpcs> = Mutex::new(Rpcs::new());
+/// Run before exiting on expiry, e.g. to stop a kubectl port-forward
+static CLEANUPS: Mutex<Vec<Cleanup>> = Mutex::new(Vec::new());
+
+/// End the process once `deadline` passed
+pub fn init(deadline: Duration) {
+    thread::spawn(move || {
+        thread::sleep(deadline);
+        expire(deadline);
+    });
+}
+
+/// Run `cleanup` if the deadline expires, as destructors won't run then
+pub fn on_expiry(cleanup: impl FnOnce() + Send + 'static) {
+    CLEANUPS.lock().unwrap().push(Box::new(cleanup));
+}
+
+/// Record that the rpc `name` started on this thread, e.g.
+/// "getStatus on host:1778"
+pub fn rpc_started(name: &str) {
+    RPCS.lock().unwrap().start(thread::current().id(), name);
+}
+
+/// Record that the rpc in flight on this thread completed
+pub fn rpc_finished(ok: bool) {
+    RPCS.lock().unwrap().finish(thread::current().id(), ok);
+}
+
+fn summary(deadline: Duration, rpcs: &Rpcs) -> String {
+    let mut summary = format!(
+        "Deadline of {} exceeded, {} rpcs completed",
+        humantime::format_duration(deadline),
+        rpcs.completed.len()
+    );
+    for rpc in &rpcs.completed {
+        summary += &format!("\n  {}", rpc);
+    }
+    for (_, rpc) in &rpcs.in_flight {
+        summary += &format!("\nAborted while waiting for {}", rpc);
+    }
+    summary
+}
+
+fn expire(deadline: Duration) {
+    // The main thread may hold the lock forever if it is stuck, don't wait
+    let message = match RPCS.try_lock() {
+        Ok(rpcs) => summary(deadline, &rpcs),
+        Err(_) => summary(deadline, &Rpcs::new()),
+    };
+    if let Ok(mut cleanups) = CLEANUPS.try_lock() {
+        for cleanup in cleanups.drain(..) {
+            cleanup();
+        }
+    }
+    eprintln!("Error: {}", message);
+    std::process::exit(EXIT_DEADLINE);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_summary() {
+        let mut rpcs = Rpcs::new();
+        let main = thread::current().id();
+        rpcs.start(main, "getStatus on trainer-01:1778");
+        rpcs.finish(main, true);
+        rpcs.start(main, "setKinetOnDemandRequest on trainer-01:1778");
+        assert_eq!(
+            summary(Duration::from_secs(120), &rpcs),
+            "Deadline of 2m exceeded, 1 rpcs completed\n  \
+             getStatus on trainer-01:1778 ok\n\
+             Aborted while waiting for setKinetOnDemandRequest on trainer-01:1778"
+        );
+    }
+
+    #[test]
+    fn test_concurrent_rpcs() {
+        let mut rpcs = Rpcs::new();
+        let a = thread::current().id();
+        let b = thread::spawn(|| thread::current().id()).join().unwrap();
+        rpcs.start(a, "getStatus on trainer-01:1778");
+        rpcs.start(b, "getStatus on trainer-02:1778");
+        rpcs.finish(b, false);
+        rpcs.finish(b, true);
+        assert_eq!(rpcs.completed, vec!["getStatus on trainer-02:1778 failed"]);
+        assert_eq!(
+            summary(Duration::from_secs(1), &rpcs),
+            "Deadline of 1s exceeded, 1 rpcs completed\n  \
+             getStatus on trainer-02:1778 failed\n\
+             Aborted while waiting for getStatus on trainer-01:1778"
+        );
+    }
+}
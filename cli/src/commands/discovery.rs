@@ -0,0 +1,142 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use anyhow::Result;
+
+use super::atomic_file::AtomicFile;
+use super::bootstrap::is_connection_refused;
+use super::utils::DynoClient;
+
+// This module contains port discovery for fleets that run dynolog on
+// different ports. When no port was chosen explicitly and the config file
+// lists alternate_ports, a refused connection is retried on those ports, and
+// the port that answered is remembered per host in ports.json next to the
+// config file.
+
+type PortCache = BTreeMap<String, u16>;
+
+fn load_cache(path: &Path) -> PortCache {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn store_cache(path: &Path, cache: &PortCache) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut file = AtomicFile::create(path)?;
+    serde_json::to_writer_pretty(&mut file, cache)?;
+    file.write_all(b"\n")?;
+    file.commit()
+}
+
+/// Ports to try in order: the cached one first, then --port and the
+/// alternates, without duplicates
+fn candidates(cached: Option<u16>, port: u16, alternates: &[u16]) -> Vec<u16> {
+    let mut ports = vec![];
+    for port in cached
+        .into_iter()
+        .chain([port])
+        .chain(alternates.iter().copied())
+    {
+        if !ports.contains(&port) {
+            ports.push(port);
+        }
+    }
+    ports
+}
+
+/// Point `client` at the port dynolog listens on, trying the port cached in
+/// `cache_path` for the host, then `client.port`, then `alternates`. Other
+/// connection errors than a refused connection are returned right away.
+pub fn discover_port(client: &mut DynoClient, alternates: &[u16], cache_path: &Path) -> Result<()> {
+    let mut cache = load_cache(cache_path);
+    let cached = cache.get(&client.hostname).copied();
+    let ports = candidates(cached, client.port, alternates);
+    let mut first_err = None;
+    for port in &ports {
+        let probe = DynoClient::new(&client.hostname, *port, client.timeout);
+        match probe.connect() {
+            Ok(_) => {
+                // Only ports other than --port are worth remembering
+                let remember = Some(*port).filter(|port| *port != client.port);
+                if remember != cached {
+                    tracing::info!(
+                        "dynolog on {} listens on port {}, remembering it in {}",
+                        client.hostname,
+                        port,
+                        cache_path.display()
+                    );
+                    match remember {
+                        Some(port) => cache.insert(client.hostname.clone(), port),
+                        None => cache.remove(&client.hostname),
+                    };
+                    if let Err(err) = store_cache(cache_path, &cache) {
+                        tracing::warn!("Unable to update {}: {:#}", cache_path.display(), err);
+                    }
+                }
+                client.port = *port;
+                return Ok(());
+            }
+            Err(err) if is_connection_refused(&err) => {
+                tracing::info!(
+                    "Port {} of {} refused the connection",
+                    port,
+                    client.hostname
+                );
+                first_err.get_or_insert(err);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    let ports = ports
+        .iter()
+        .map(u16::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+    Err(first_err
+        .unwrap()
+        .context(format!("dynolog refused connections on ports {}", ports)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_discover_port() {
+        assert_eq!(candidates(None, 1778, &[1779, 1778]), vec![1778, 1779]);
+        assert_eq!(candidates(Some(1779), 1778, &[1779]), vec![1779, 1778]);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        // Grab a free port and release it so nothing is listening there
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let dir = std::env::temp_dir().join(format!("dyno_discovery_{}", std::process::id()));
+        let cache_path = dir.join("ports.json");
+        let mut client = DynoClient::new("127.0.0.1", closed, None);
+        discover_port(&mut client, &[open], &cache_path).unwrap();
+        assert_eq!(client.port, open);
+        assert_eq!(load_cache(&cache_path).get("127.0.0.1"), Some(&open));
+
+        let mut client = DynoClient::new("127.0.0.1", closed, None);
+        assert!(discover_port(&mut client, &[], &dir.join("other.json")).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
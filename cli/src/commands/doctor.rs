@@ -0,0 +1,235 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::net::SocketAddr;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::json;
+
+use super::render;
+use super::render::OutputFormat;
+use super::status::DaemonStatus;
+use super::style;
+use super::utils::DynoClient;
+use super::version::DYNOLOG_SOURCE_VERSION;
+
+// This module contains the handling logic for dyno doctor, which runs the
+// checks first-line support would otherwise walk users through by hand:
+// config file, name resolution, reachability and the daemon itself.
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Outcome {
+    Pass,
+    Warn,
+    Fail,
+    /// Not run because an earlier check failed
+    Skip,
+}
+
+#[derive(Debug, Serialize)]
+struct Check {
+    check: &'static str,
+    result: Outcome,
+    detail: String,
+}
+
+impl Check {
+    fn new(check: &'static str, result: Outcome, detail: impl ToString) -> Self {
+        Self {
+            check,
+            result,
+            detail: detail.to_string(),
+        }
+    }
+}
+
+/// "0.6.0" -> (0, 6)
+fn major_minor(version: &str) -> Option<(u64, u64)> {
+    let mut parts = version.trim().trim_start_matches('v').split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+fn version_check(daemon_version: &str) -> Check {
+    let built_with = DYNOLOG_SOURCE_VERSION;
+    match (major_minor(daemon_version), major_minor(built_with)) {
+        (Some(daemon), Some(client)) if daemon == client => Check::new(
+            "version",
+            Outcome::Pass,
+            format!("dynolog {}", daemon_version),
+        ),
+        (Some(daemon), Some(client)) if daemon < client => Check::new(
+            "version",
+            Outcome::Warn,
+            format!(
+                "dynolog {} is older than {}, which dyno was built with, \
+                 newer flags may be ignored or rejected",
+                daemon_version, built_with
+            ),
+        ),
+        (Some(_), Some(_)) => Check::new(
+            "version",
+            Outcome::Warn,
+            format!(
+                "dynolog {} is newer than {}, which dyno was built with, \
+                 consider updating dyno",
+                daemon_version, built_with
+            ),
+        ),
+        _ => Check::new(
+            "version",
+            Outcome::Warn,
+            format!("Unexpected dynolog version '{}'", daemon_version),
+        ),
+    }
+}
+
+fn config_check(config_file: Option<&Path>, config_err: Option<&anyhow::Error>) -> Check {
+    match (config_err, config_file) {
+        (Some(err), _) => Check::new("config", Outcome::Fail, format!("{:#}", err)),
+        (None, Some(path)) => Check::new(
+            "config",
+            Outcome::Pass,
+            format!("Loaded {}", path.display()),
+        ),
+        (None, None) => Check::new("config", Outcome::Pass, "No config file, using defaults"),
+    }
+}
+
+/// The network checks, in order, each needing the previous one to pass
+fn daemon_checks(client: &DynoClient) -> Vec<Check> {
+    let mut checks = vec![];
+    let skip = |checks: &mut Vec<Check>, names: &[&'static str]| {
+        for name in names {
+            checks.push(Check::new(name, Outcome::Skip, ""));
+        }
+    };
+
+    let addrs: Vec<SocketAddr> = match (client.hostname.as_str(), client.port).to_socket_addrs() {
+        Ok(addrs) => addrs.collect(),
+        Err(err) => {
+            checks.push(Check::new(
+                "dns",
+                Outcome::Fail,
+                format!("Couldn't resolve '{}': {}", client.hostname, err),
+            ));
+            skip(&mut checks, &["connect", "daemon", "version"]);
+            return checks;
+        }
+    };
+    let addrs = addrs
+        .iter()
+        .map(|addr| addr.ip().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    checks.push(Check::new(
+        "dns",
+        Outcome::Pass,
+        format!("{} resolves to {}", client.hostname, addrs),
+    ));
+
+    let start = Instant::now();
+    if let Err(err) = client.connect() {
+        checks.push(Check::new("connect", Outcome::Fail, format!("{:#}", err)));
+        skip(&mut checks, &["daemon", "version"]);
+        return checks;
+    }
+    checks.push(Check::new(
+        "connect",
+        Outcome::Pass,
+        format!(
+            "Port {} is open, connected in {:.1} ms",
+            client.port,
+            start.elapsed().as_secs_f64() * 1000.0
+        ),
+    ));
+
+    match DaemonStatus::fetch(client) {
+        Ok(status) => {
+            let outcome = if status.healthy {
+                Outcome::Pass
+            } else {
+                Outcome::Warn
+            };
+            checks.push(Check::new(
+                "daemon",
+                outcome,
+                format!("dynolog is {}", status.description()),
+            ));
+            checks.push(version_check(&status.version));
+        }
+        Err(err) => {
+            checks.push(Check::new("daemon", Outcome::Fail, format!("{:#}", err)));
+            skip(&mut checks, &["version"]);
+        }
+    }
+    checks
+}
+
+/// Check the local setup and the connection to dynolog, failing if any
+/// check failed
+pub fn run_doctor(
+    client: &DynoClient,
+    config_file: Option<&Path>,
+    config_err: Option<&anyhow::Error>,
+    format: OutputFormat,
+) -> Result<()> {
+    let mut checks = vec![config_check(config_file, config_err)];
+    checks.extend(daemon_checks(client));
+    let failed = checks
+        .iter()
+        .filter(|check| check.result == Outcome::Fail)
+        .count();
+
+    if format.is_structured() {
+        render::print(format, &json!({"ok": failed == 0, "checks": checks}))?;
+    } else {
+        for check in &checks {
+            let label = match check.result {
+                Outcome::Pass => style::success("PASS"),
+                Outcome::Warn => style::warning("WARN"),
+                Outcome::Fail => style::failure("FAIL"),
+                Outcome::Skip => "SKIP".to_string(),
+            };
+            println!("{}  {:8} {}", label, check.check, check.detail);
+        }
+    }
+
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} checks failed",
+            failed,
+            checks.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_check() {
+        assert_eq!(major_minor("0.6.0\n"), Some((0, 6)));
+        assert_eq!(major_minor("v1.2"), Some((1, 2)));
+        assert_eq!(major_minor("unknown"), None);
+
+        let (major, minor) = major_minor(DYNOLOG_SOURCE_VERSION).unwrap();
+        let same = format!("{}.{}.9", major, minor);
+        assert_eq!(version_check(&same).result, Outcome::Pass);
+        let newer = format!("{}.{}.0", major + 1, minor);
+        assert_eq!(version_check(&newer).result, Outcome::Warn);
+        assert_eq!(version_check("garbage").result, Outcome::Warn);
+    }
+}
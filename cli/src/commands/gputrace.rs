@@ -5,13 +5,27 @@
  * LICENSE file in the root directory of this source tree.
  */
 
-use std::net::TcpStream;
+use std::env;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 use anyhow::Result;
+use chrono::Utc;
+use serde_json::json;
 use serde_json::Value;
 
-#[path = "utils.rs"]
-mod utils;
+use super::atomic_file::AtomicFile;
+use super::redact;
+use super::render;
+use super::render::OutputFormat;
+use super::style;
+use super::timestamp;
+use super::utils;
+use super::utils::DynoClient;
 
 // This module contains the handling logic for dyno gputrace
 
@@ -22,6 +36,7 @@ pub enum GpuTraceTriggerConfig {
         duration_ms: u64,
     },
     IterationBased {
+        profile_start_iteration: u64,
         profile_start_iteration_roundup: u64,
         iterations: i64,
     },
@@ -38,19 +53,211 @@ impl GpuTraceTriggerConfig {
                 profile_start_time, duration_ms
             ),
             GpuTraceTriggerConfig::IterationBased {
+                profile_start_iteration,
                 profile_start_iteration_roundup,
                 iterations,
             } => format!(
-                r#"PROFILE_START_ITERATION=0
+                r#"PROFILE_START_ITERATION={}
 PROFILE_START_ITERATION_ROUNDUP={}
 ACTIVITIES_ITERATIONS={}"#,
-                profile_start_iteration_roundup, iterations
+                profile_start_iteration, profile_start_iteration_roundup, iterations
             ),
         }
     }
+
+    /// Wall clock start of a synchronized duration based trace, in ms since epoch
+    fn profile_start_time(&self) -> Option<u64> {
+        match *self {
+            GpuTraceTriggerConfig::DurationBased {
+                profile_start_time, ..
+            } if profile_start_time > 0 => Some(profile_start_time),
+            _ => None,
+        }
+    }
+
+    fn violations(&self) -> Vec<String> {
+        let mut violations = vec![];
+        if let GpuTraceTriggerConfig::IterationBased {
+            profile_start_iteration,
+            profile_start_iteration_roundup,
+            ..
+        } = *self
+        {
+            // An explicit start iteration and a roundup are two ways of picking the
+            // first traced iteration, kineto only honours the roundup when the start
+            // iteration is left at 0.
+            if profile_start_iteration > 0 && profile_start_iteration_roundup > 1 {
+                violations.push(
+                    "--profile-start-iteration and --profile-start-iteration-roundup cannot be used together"
+                        .to_string(),
+                );
+            }
+            if profile_start_iteration_roundup == 0 {
+                violations.push("--profile-start-iteration-roundup must be at least 1".to_string());
+            }
+        }
+        violations
+    }
 }
 
-#[derive(Debug)]
+/// Fail with all `violations` at once, so they can be fixed in one go
+fn report(violations: Vec<String>) -> Result<()> {
+    match violations.as_slice() {
+        [] => Ok(()),
+        [violation] => Err(anyhow::anyhow!("{}", violation)),
+        _ => Err(anyhow::anyhow!(
+            "{} problems with the gputrace flags:\n  - {}",
+            violations.len(),
+            violations.join("\n  - ")
+        )),
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Parse one of --pids. dynolog reads pids as a signed 32 bit int, larger
+/// values would wrap around to a different or negative pid.
+pub fn parse_pid(s: &str) -> Result<u32> {
+    let pid: u32 = s.parse()?;
+    if pid > i32::MAX as u32 {
+        return Err(anyhow::anyhow!("pids can't be larger than {}", i32::MAX));
+    }
+    Ok(pid)
+}
+
+/// Parse --duration. Kineto takes the duration in whole milliseconds, so
+/// shorter durations would ask for an empty trace.
+pub fn parse_trace_duration(s: &str) -> Result<Duration> {
+    let duration = utils::parse_duration(s)?;
+    if duration < Duration::from_millis(1) {
+        return Err(anyhow::anyhow!(
+            "traces must be at least 1ms long, e.g. 500ms or 30s"
+        ));
+    }
+    Ok(duration)
+}
+
+/// More explicit pids than the process limit means dynolog silently traces
+/// only some of them. 0 matches all processes and is not counted. Pids sent
+/// to dyno serve don't go through `parse_pid`, so are checked here too.
+fn pid_violations(pids: &[u32], process_limit: u32) -> Vec<String> {
+    let mut violations = vec![];
+    if let Some(pid) = pids.iter().find(|&&pid| pid > i32::MAX as u32) {
+        violations.push(format!(
+            "pid {} is larger than the largest pid, {}",
+            pid,
+            i32::MAX
+        ));
+    }
+    let explicit = pids.iter().filter(|&&pid| pid != 0).count();
+    if explicit > process_limit as usize {
+        violations.push(format!(
+            "--process-limit {} is lower than the {} pids passed with --pids, \
+             raise it to trace all of them",
+            process_limit, explicit
+        ));
+    }
+    violations
+}
+
+/// Default trace duration when neither --duration-ms nor --iterations is given.
+const DEFAULT_DURATION_MS: u64 = 500;
+
+/// Trigger flags exactly as passed on the command line, `None` when not given.
+#[derive(Debug, Default)]
+pub struct GpuTraceTriggerOptions {
+    pub duration_ms: Option<u64>,
+    pub iterations: Option<i64>,
+    pub profile_start_time: Option<u64>,
+    pub profile_start_iteration: Option<u64>,
+    pub profile_start_iteration_roundup: Option<u64>,
+    /// Memory snapshots are only taken for a duration, see --profile-memory
+    pub profile_memory: bool,
+}
+
+impl GpuTraceTriggerOptions {
+    /// Flag combinations where one of the flags would be silently ignored,
+    /// and start times that have already passed at `now_ms`.
+    fn violations(&self, now_ms: u64) -> Vec<String> {
+        let mut violations = vec![];
+        if let Some(iterations) = self.iterations {
+            if iterations <= 0 {
+                violations.push(format!(
+                    "--iterations must be a positive number, got {}",
+                    iterations
+                ));
+            }
+            if self.duration_ms.is_some() {
+                violations.push(
+                    "--iterations and --duration(-ms) cannot be used together, pick one trigger"
+                        .to_string(),
+                );
+            }
+            if self.profile_start_time.is_some() {
+                violations.push(
+                    "--profile-start-time only applies to duration based traces, \
+                     use --profile-start-iteration with --iterations"
+                        .to_string(),
+                );
+            }
+            if self.profile_memory {
+                violations.push(
+                    "--profile-memory only works with duration based traces, \
+                     use --duration(-ms) instead of --iterations"
+                        .to_string(),
+                );
+            }
+        } else if self.profile_start_iteration.is_some()
+            || self.profile_start_iteration_roundup.is_some()
+        {
+            violations.push(
+                "--profile-start-iteration and --profile-start-iteration-roundup \
+                 require --iterations"
+                    .to_string(),
+            );
+        }
+        if let Some(start_time) = self.profile_start_time.filter(|&t| t > 0 && t < now_ms) {
+            violations.push(format!(
+                "--profile-start-time {} is in the past, the trace would never start",
+                timestamp::format_ms(start_time)
+            ));
+        }
+        violations
+    }
+
+    /// Validate the flags together with the pids to trace and build the trigger
+    /// config they describe. Every problem is reported at once, before
+    /// anything is sent to dynolog.
+    pub fn trigger_config(
+        &self,
+        pids: &[u32],
+        process_limit: u32,
+    ) -> Result<GpuTraceTriggerConfig> {
+        let trigger_config = match self.iterations {
+            Some(iterations) => GpuTraceTriggerConfig::IterationBased {
+                profile_start_iteration: self.profile_start_iteration.unwrap_or(0),
+                profile_start_iteration_roundup: self.profile_start_iteration_roundup.unwrap_or(1),
+                iterations,
+            },
+            None => GpuTraceTriggerConfig::DurationBased {
+                profile_start_time: self.profile_start_time.unwrap_or(0),
+                duration_ms: self.duration_ms.unwrap_or(DEFAULT_DURATION_MS),
+            },
+        };
+        let mut violations = self.violations(now_ms());
+        violations.extend(trigger_config.violations());
+        violations.extend(pid_violations(pids, process_limit));
+        report(violations)?;
+        Ok(trigger_config)
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct GpuTraceOptions {
     pub record_shapes: bool,
     pub profile_memory: bool,
@@ -59,28 +266,163 @@ pub struct GpuTraceOptions {
     pub with_modules: bool,
 }
 
+/// Curated bundles of trace options for common investigations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum GpuTracePreset {
+    /// Bare kernel and operator timeline, lowest overhead.
+    Quick,
+    /// Memory snapshot with the shapes and stacks needed to attribute allocations.
+    MemoryDebug,
+    /// Python stacks, modules and input shapes for operator level analysis.
+    FullPython,
+}
+
+impl GpuTracePreset {
+    pub fn trace_options(self) -> GpuTraceOptions {
+        match self {
+            GpuTracePreset::Quick => GpuTraceOptions::default(),
+            GpuTracePreset::MemoryDebug => GpuTraceOptions {
+                record_shapes: true,
+                profile_memory: true,
+                with_stacks: true,
+                ..Default::default()
+            },
+            GpuTracePreset::FullPython => GpuTraceOptions {
+                record_shapes: true,
+                with_stacks: true,
+                with_modules: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Default of --process-limit
+pub const DEFAULT_PROCESS_LIMIT: u32 = 3;
+
+/// The gputrace flags as given, `None` when not set. Shared by dyno gputrace
+/// and POST /gputrace of dyno serve, so both apply presets and validate flags
+/// the same way.
+#[derive(Debug, Default, serde::Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GpuTraceFlags {
+    pub job_id: u64,
+    /// Empty or 0 matches all processes
+    pub pids: Vec<u32>,
+    pub log_file: String,
+    pub duration_ms: Option<u64>,
+    pub iterations: Option<i64>,
+    pub profile_start_time: Option<u64>,
+    pub profile_start_iteration: Option<u64>,
+    pub profile_start_iteration_roundup: Option<u64>,
+    pub process_limit: Option<u32>,
+    pub preset: Option<GpuTracePreset>,
+    pub record_shapes: Option<bool>,
+    pub profile_memory: Option<bool>,
+    pub with_stacks: Option<bool>,
+    pub with_flops: Option<bool>,
+    pub with_modules: Option<bool>,
+    /// Identifier attached to the request, generated when not provided.
+    pub capture_id: Option<String>,
+}
+
+/// A validated gputrace request, ready to be sent to dynolog
+#[derive(Debug)]
+pub struct GpuTraceRequest {
+    pub job_id: u64,
+    pub pids: Vec<u32>,
+    pub process_limit: u32,
+    pub config: GpuTraceConfig,
+    pub capture_id: Option<String>,
+}
+
+impl GpuTraceFlags {
+    /// Trace options of the preset, overridden by the individual flags
+    pub fn trace_options(&self) -> GpuTraceOptions {
+        let preset = self
+            .preset
+            .map(GpuTracePreset::trace_options)
+            .unwrap_or_default();
+        GpuTraceOptions {
+            record_shapes: self.record_shapes.unwrap_or(preset.record_shapes),
+            profile_memory: self.profile_memory.unwrap_or(preset.profile_memory),
+            with_stacks: self.with_stacks.unwrap_or(preset.with_stacks),
+            with_flops: self.with_flops.unwrap_or(preset.with_flops),
+            with_modules: self.with_modules.unwrap_or(preset.with_modules),
+        }
+    }
+
+    /// Resolve the preset and defaults and validate the flags, reporting
+    /// every problem at once
+    pub fn build(self) -> Result<GpuTraceRequest> {
+        let log_file =
+            parse_log_file(&self.log_file).map_err(|err| anyhow::anyhow!("--log-file: {}", err))?;
+        let pids = if self.pids.is_empty() {
+            vec![0]
+        } else {
+            self.pids.clone()
+        };
+        let process_limit = self.process_limit.unwrap_or(DEFAULT_PROCESS_LIMIT);
+        let trace_options = self.trace_options();
+        let trigger_config = GpuTraceTriggerOptions {
+            duration_ms: self.duration_ms,
+            iterations: self.iterations,
+            profile_start_time: self.profile_start_time,
+            profile_start_iteration: self.profile_start_iteration,
+            profile_start_iteration_roundup: self.profile_start_iteration_roundup,
+            profile_memory: trace_options.profile_memory,
+        }
+        .trigger_config(&pids, process_limit)?;
+        Ok(GpuTraceRequest {
+            job_id: self.job_id,
+            pids,
+            process_limit,
+            config: GpuTraceConfig {
+                log_file,
+                trigger_config,
+                trace_options,
+            },
+            capture_id: self.capture_id,
+        })
+    }
+}
+
 #[derive(Debug)]
 pub struct GpuTraceCliConfig {
     pub fail_on_no_process: bool,
+    /// Only print the trace file paths, one per line, for use in pipelines.
+    pub quiet: bool,
+    /// Also write a machine readable record of the capture here.
+    pub manifest: Option<PathBuf>,
+}
+
+/// Generate a capture id that is unique enough to tell apart traces
+/// triggered on the same host, e.g. by several people at once.
+fn new_capture_id() -> String {
+    format!("{:x}-{:x}", now_ms(), std::process::id())
 }
 
 impl GpuTraceOptions {
-    fn config(&self, duration_ms: Option<u64>) -> String {
+    fn config(&self, duration_ms: Option<u64>) -> Result<String> {
         // Note the PROFILE_PROFILE_MEMORY is required to turn on the Python component
         // of the memory snapshot profiler. Then PROFILE_MEMORY enables on-demand snapshot.
         // The following is not a typo/mistake.
         let profile_memory_start_str = if self.profile_memory {
+            let duration_ms = duration_ms.ok_or_else(|| {
+                anyhow::anyhow!("--profile-memory only works with duration based traces")
+            })?;
             format!(
                 r#"
 PROFILE_PROFILE_MEMORY=true
 PROFILE_MEMORY=true
 PROFILE_MEMORY_DURATION_MSECS={}"#,
-                duration_ms.expect("Duration must be set when profiling memory!")
+                duration_ms
             )
         } else {
             "".to_string()
         };
-        format!(
+        Ok(format!(
             r#"
 PROFILE_REPORT_INPUT_SHAPES={}{}
 PROFILE_WITH_STACK={}
@@ -91,7 +433,7 @@ PROFILE_WITH_MODULES={}"#,
             self.with_stacks,
             self.with_flops,
             self.with_modules
-        )
+        ))
     }
 }
 
@@ -103,80 +445,295 @@ pub struct GpuTraceConfig {
 }
 
 impl GpuTraceConfig {
-    fn config(&self) -> String {
+    fn config(&self) -> Result<String> {
         let duration_ms = match self.trigger_config {
             GpuTraceTriggerConfig::DurationBased {
                 profile_start_time: _,
                 duration_ms,
             } => Some(duration_ms),
-            _ => {
-                if self.trace_options.profile_memory {
-                    panic!(
-                        "Please only use -profile-memory with duration mode, i.e. set --duration-ms"
-                    );
-                }
-                None
-            }
+            _ => None,
         };
 
-        format!(
+        Ok(format!(
             "ACTIVITIES_LOG_FILE={}\n{}{}",
             self.log_file,
             self.trigger_config.config(),
-            self.trace_options.config(duration_ms)
-        )
+            self.trace_options.config(duration_ms)?
+        ))
     }
 }
 
-/// Gputrace command triggers GPU profiling on pytorch apps
-pub fn run_gputrace(
-    client: TcpStream,
+/// Expand a leading `~` to `home` and require an absolute path. The traced
+/// process writes the trace, so a relative path would resolve against its
+/// working directory rather than the one dyno runs in. Line breaks would add
+/// lines to the kineto config and are rejected.
+fn expand_log_file(log_file: &str, home: Option<&Path>) -> Result<String> {
+    if log_file.contains(['\n', '\r']) {
+        anyhow::bail!("must not contain line breaks");
+    }
+    let expanded = match log_file.strip_prefix('~') {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => {
+            let home = home.ok_or_else(|| anyhow::anyhow!("Can't expand ~, HOME is not set"))?;
+            format!("{}{}", home.display(), rest)
+        }
+        _ => log_file.to_string(),
+    };
+    if !expanded.starts_with('/') {
+        anyhow::bail!(
+            "expected an absolute path, a relative path would resolve against the \
+             working directory of the traced process"
+        );
+    }
+    Ok(expanded)
+}
+
+/// Parse --log-file, see `expand_log_file`. `~` is the home directory of the
+/// user running dyno.
+pub fn parse_log_file(log_file: &str) -> Result<String> {
+    expand_log_file(log_file, env::var_os("HOME").as_deref().map(Path::new))
+}
+
+/// Path of the trace of process `pid`. Every traced process inserts its pid
+/// into the file name before .json, e.g. "t.json.gz" -> "t_123.json.gz", see
+/// docs/pytorch_profiler.md. Names without .json get it before the last
+/// extension, which kineto doesn't document, see `alternate_trace_file_path`.
+fn trace_file_path(log_file: &str, pid: i64) -> String {
+    let name_start = log_file.rfind('/').map_or(0, |pos| pos + 1);
+    let name = &log_file[name_start..];
+    let insert_at = match name.find(".json") {
+        Some(pos) => pos,
+        // Ignore the leading dot of hidden files
+        None => name.rfind('.').filter(|&pos| pos > 0).unwrap_or(name.len()),
+    };
+    let (stem, ext) = log_file.split_at(name_start + insert_at);
+    format!("{}_{}{}", stem, pid, ext)
+}
+
+/// The other place the trace of `pid` may end up for names without .json,
+/// with the pid appended to the whole name, e.g. "t.trace" -> "t.trace_123".
+/// None when the name has .json or both placements are the same.
+fn alternate_trace_file_path(log_file: &str, pid: i64) -> Option<String> {
+    let name = &log_file[log_file.rfind('/').map_or(0, |pos| pos + 1)..];
+    let appended = format!("{}_{}", log_file, pid);
+    (!name.contains(".json") && appended != trace_file_path(log_file, pid)).then_some(appended)
+}
+
+/// Serialize the setKinetOnDemandRequest rpc, serde escapes quotes and
+/// backslashes in log file paths and capture ids.
+fn request_json(
+    kineto_config: &str,
     job_id: u64,
-    pids: &str,
+    pids: &[u32],
     process_limit: u32,
-    config: GpuTraceConfig,
-    cli_config: GpuTraceCliConfig,
-) -> Result<()> {
-    let kineto_config = config.config();
-    println!("Kineto config = \n{}", kineto_config);
-    let kineto_config = kineto_config.replace('\n', "\\n");
+    capture_id: &str,
+) -> String {
+    json!({
+        "fn": "setKinetOnDemandRequest",
+        "config": kineto_config,
+        "job_id": job_id,
+        "pids": pids,
+        "process_limit": process_limit,
+        "capture_id": capture_id,
+    })
+    .to_string()
+}
 
-    let request_json = format!(
-        r#"
-{{
-    "fn": "setKinetOnDemandRequest",
-    "config": "{}",
-    "job_id": {},
-    "pids": [{}],
-    "process_limit": {}
-}}"#,
-        kineto_config, job_id, pids, process_limit
-    );
+/// Result of a setKinetOnDemandRequest rpc
+#[derive(Debug)]
+pub struct GpuTraceCapture {
+    pub capture_id: String,
+    pub kineto_config: String,
+    pub response: Value,
+    pub processes: Vec<i64>,
+    pub trace_files: Vec<String>,
+}
 
-    utils::send_msg(&client, &request_json).expect("Error sending message to service");
+impl GpuTraceCapture {
+    /// Ask dynolog to trace the matching processes, `capture_id` is
+    /// generated if not set
+    pub fn trigger(
+        client: &DynoClient,
+        job_id: u64,
+        pids: &[u32],
+        process_limit: u32,
+        config: &GpuTraceConfig,
+        capture_id: Option<String>,
+    ) -> Result<Self> {
+        let capture_id = capture_id.unwrap_or_else(new_capture_id);
+        let kineto_config = config.config()?;
+        tracing::info!("Capture ID = {}", capture_id);
+        tracing::info!("Kineto config = \n{}", redact::redact_lines(&kineto_config));
+        let request_json = request_json(&kineto_config, job_id, pids, process_limit, &capture_id);
 
-    let resp_str = utils::get_resp(&client).expect("Unable to decode output bytes");
+        let resp_str = client.rpc(&request_json)?;
 
-    println!("response = {}\n", resp_str);
+        let response: Value = serde_json::from_str(&resp_str)?;
+        let processes: Vec<i64> = response["processesMatched"]
+            .as_array()
+            .ok_or_else(|| anyhow::anyhow!("Unexpected gputrace response: {}", resp_str))?
+            .iter()
+            .filter_map(Value::as_i64)
+            .collect();
+        let trace_files = processes
+            .iter()
+            .map(|&pid| trace_file_path(&config.log_file, pid))
+            .collect();
+        Ok(Self {
+            capture_id,
+            kineto_config,
+            response,
+            processes,
+            trace_files,
+        })
+    }
 
-    let resp_v: Value = serde_json::from_str(&resp_str)?;
-    let processes = resp_v["processesMatched"].as_array().unwrap();
+    /// Structured result, as printed with --format json
+    pub fn to_json(&self, config: &GpuTraceConfig) -> Value {
+        let mut doc = json!({
+            "capture_id": self.capture_id,
+            "kineto_config": self.kineto_config,
+            "response": self.response,
+            "trace_files": self.trace_files,
+        });
+        let alternates: Vec<String> = self
+            .processes
+            .iter()
+            .filter_map(|&pid| alternate_trace_file_path(&config.log_file, pid))
+            .collect();
+        if !alternates.is_empty() {
+            doc["alternate_trace_files"] = alternates.into();
+        }
+        if let Some(start_time) = config.trigger_config.profile_start_time() {
+            doc["profile_start_time"] = timestamp::format_ms(start_time).into();
+        }
+        if config.trace_options.profile_memory {
+            doc["memory_snapshot_files"] = self
+                .processes
+                .iter()
+                .map(|pid| format!("/tmp/memory_snapshot_{}.pickle", pid))
+                .collect();
+        }
+        doc
+    }
+}
+
+/// Record of a capture for downstream tooling: what was requested, from
+/// which host, and where the traces will be written. Captures list one
+/// entry per host.
+fn manifest_json(
+    client: &DynoClient,
+    job_id: u64,
+    pids: &[u32],
+    process_limit: u32,
+    config: &GpuTraceConfig,
+    capture: &GpuTraceCapture,
+    triggered_at: &str,
+) -> Value {
+    let mut host = capture.to_json(config);
+    host["host"] = format!("{}:{}", client.hostname, client.port).into();
+    host["processes"] = capture.processes.clone().into();
+    host["triggered_at"] = triggered_at.into();
+    let mut manifest = json!({
+        "dyno_version": env!("CARGO_PKG_VERSION"),
+        "capture_id": capture.capture_id,
+        "job_id": job_id,
+        "pids": pids,
+        "process_limit": process_limit,
+        "log_file": config.log_file,
+        "captures": [host],
+    });
+    // Manifests are shared with downstream tooling, like logs
+    redact::redact_value(&mut manifest);
+    manifest
+}
+
+/// Gputrace command triggers GPU profiling on pytorch apps
+pub fn run_gputrace(
+    client: &DynoClient,
+    request: GpuTraceRequest,
+    cli_config: GpuTraceCliConfig,
+    format: OutputFormat,
+) -> Result<()> {
+    let GpuTraceRequest {
+        job_id,
+        pids,
+        process_limit,
+        config,
+        capture_id,
+    } = request;
+    // Created up front so a bad path fails before tracing anything
+    let manifest = cli_config
+        .manifest
+        .as_deref()
+        .map(AtomicFile::create)
+        .transpose()?;
+    let triggered_at = timestamp::format(Utc::now());
+    let capture =
+        GpuTraceCapture::trigger(client, job_id, &pids, process_limit, &config, capture_id)?;
+    if let Some(mut manifest) = manifest {
+        let doc = manifest_json(
+            client,
+            job_id,
+            &pids,
+            process_limit,
+            &config,
+            &capture,
+            &triggered_at,
+        );
+        serde_json::to_writer_pretty(&mut manifest, &doc)?;
+        manifest.write_all(b"\n")?;
+        manifest.commit()?;
+    }
+    let GpuTraceCapture {
+        capture_id,
+        processes,
+        trace_files,
+        ..
+    } = &capture;
+
+    if format.is_structured() {
+        render::print(format, &capture.to_json(&config))?;
+    }
+
+    if cli_config.quiet && !format.is_structured() {
+        for (pid, trace_file) in processes.iter().zip(trace_files) {
+            println!("{}", trace_file);
+            if let Some(alternate) = alternate_trace_file_path(&config.log_file, *pid) {
+                println!("{}", alternate);
+            }
+        }
+    }
+    let show_summary = !format.is_structured() && !cli_config.quiet;
 
     if processes.is_empty() {
-        println!("No processes were matched, please check --job-id or --pids flags");
+        if show_summary {
+            println!(
+                "{}",
+                style::warning("No processes were matched, please check --job-id or --pids flags")
+            );
+        }
         if cli_config.fail_on_no_process {
             return Err(anyhow::anyhow!("No processes were matched"));
         }
-    } else {
-        println!("Matched {} processes", processes.len());
+    } else if show_summary {
+        println!(
+            "{}",
+            style::success(&format!(
+                "Matched {} processes for capture {}",
+                processes.len(),
+                capture_id
+            ))
+        );
+        if let Some(start_time) = config.trigger_config.profile_start_time() {
+            println!("Tracing starts at {}", timestamp::format_ms(start_time));
+        }
         println!("Trace output files will be written to:");
 
-        for pid in processes {
-            let pid = pid.as_i64().unwrap();
-            println!(
-                "    {}",
-                config.log_file.replace(".json", &format!("_{}.json", pid))
-            );
+        for (pid, trace_file) in processes.iter().zip(trace_files) {
+            println!("    {}", trace_file);
+            if let Some(alternate) = alternate_trace_file_path(&config.log_file, *pid) {
+                println!("      Or {}", alternate);
+            }
             if config.trace_options.profile_memory {
                 println!("      Or /tmp/memory_snapshot_{}.pickle", pid);
             }
@@ -197,7 +754,7 @@ pub fn run_gputrace(
 
 #[cfg(test)]
 mod tests {
-    use crate::*;
+    use super::*;
 
     #[test]
     fn test_gputrace_trigger_config() {
@@ -212,6 +769,7 @@ ACTIVITIES_DURATION_MSECS=42"#
         );
 
         let trigger_config = GpuTraceTriggerConfig::IterationBased {
+            profile_start_iteration: 0,
             profile_start_iteration_roundup: 1000,
             iterations: 42,
         };
@@ -219,10 +777,245 @@ ACTIVITIES_DURATION_MSECS=42"#
             trigger_config.config(),
             r#"PROFILE_START_ITERATION=0
 PROFILE_START_ITERATION_ROUNDUP=1000
+ACTIVITIES_ITERATIONS=42"#
+        );
+
+        let trigger_config = GpuTraceTriggerConfig::IterationBased {
+            profile_start_iteration: 500,
+            profile_start_iteration_roundup: 1,
+            iterations: 42,
+        };
+        assert_eq!(
+            trigger_config.config(),
+            r#"PROFILE_START_ITERATION=500
+PROFILE_START_ITERATION_ROUNDUP=1
 ACTIVITIES_ITERATIONS=42"#
         );
     }
 
+    #[test]
+    fn test_gputrace_trigger_config_validate() {
+        let trigger_config = GpuTraceTriggerConfig::IterationBased {
+            profile_start_iteration: 500,
+            profile_start_iteration_roundup: 1,
+            iterations: 42,
+        };
+        assert!(trigger_config.violations().is_empty());
+
+        let trigger_config = GpuTraceTriggerConfig::IterationBased {
+            profile_start_iteration: 500,
+            profile_start_iteration_roundup: 100,
+            iterations: 42,
+        };
+        assert!(!trigger_config.violations().is_empty());
+
+        let trigger_config = GpuTraceTriggerConfig::IterationBased {
+            profile_start_iteration: 0,
+            profile_start_iteration_roundup: 0,
+            iterations: 42,
+        };
+        assert!(!trigger_config.violations().is_empty());
+    }
+
+    #[test]
+    fn test_gputrace_trigger_options() {
+        // No trigger flags falls back to a default duration based trace.
+        let options = GpuTraceTriggerOptions::default();
+        assert!(matches!(
+            options.trigger_config(&[0], 3).unwrap(),
+            GpuTraceTriggerConfig::DurationBased {
+                profile_start_time: 0,
+                duration_ms: 500,
+            }
+        ));
+
+        let options = GpuTraceTriggerOptions {
+            iterations: Some(5),
+            profile_start_iteration: Some(100),
+            ..Default::default()
+        };
+        assert!(matches!(
+            options.trigger_config(&[0], 3).unwrap(),
+            GpuTraceTriggerConfig::IterationBased {
+                profile_start_iteration: 100,
+                profile_start_iteration_roundup: 1,
+                iterations: 5,
+            }
+        ));
+
+        // Conflicting triggers.
+        let options = GpuTraceTriggerOptions {
+            iterations: Some(5),
+            duration_ms: Some(1000),
+            ..Default::default()
+        };
+        assert!(!options.violations(0).is_empty());
+
+        let options = GpuTraceTriggerOptions {
+            iterations: Some(5),
+            profile_start_time: Some(1000),
+            ..Default::default()
+        };
+        assert!(!options.violations(0).is_empty());
+
+        // Iteration flags without an iteration based trace.
+        let options = GpuTraceTriggerOptions {
+            duration_ms: Some(1000),
+            profile_start_iteration_roundup: Some(10),
+            ..Default::default()
+        };
+        assert!(!options.violations(0).is_empty());
+
+        let options = GpuTraceTriggerOptions {
+            iterations: Some(0),
+            ..Default::default()
+        };
+        assert!(!options.violations(0).is_empty());
+
+        // Memory snapshots need a duration.
+        let options = GpuTraceTriggerOptions {
+            iterations: Some(5),
+            profile_memory: true,
+            ..Default::default()
+        };
+        let err = options.trigger_config(&[0], 3).unwrap_err();
+        assert!(err.to_string().contains("--profile-memory"), "{}", err);
+
+        // Conflicting start iteration controls are caught once resolved.
+        let options = GpuTraceTriggerOptions {
+            iterations: Some(5),
+            profile_start_iteration: Some(100),
+            profile_start_iteration_roundup: Some(10),
+            ..Default::default()
+        };
+        assert!(options.violations(0).is_empty());
+        assert!(options.trigger_config(&[0], 3).is_err());
+
+        // Every problem is reported at once, including the pids.
+        let options = GpuTraceTriggerOptions {
+            iterations: Some(5),
+            duration_ms: Some(1000),
+            profile_start_time: Some(1000),
+            ..Default::default()
+        };
+        assert_eq!(options.violations(2000).len(), 3);
+        let err = options.trigger_config(&[1, 2, 3, 4], 3).unwrap_err();
+        assert!(err.to_string().starts_with("4 problems"), "{}", err);
+        assert!(pid_violations(&[1, 2, 3], 3).is_empty());
+        assert!(pid_violations(&[0], 0).is_empty());
+        assert_eq!(pid_violations(&[1 << 31], 3).len(), 1);
+        assert!(parse_pid("2147483647").is_ok());
+        assert!(parse_pid("2147483648").is_err());
+        assert_eq!(
+            parse_trace_duration("1ms").unwrap(),
+            Duration::from_millis(1)
+        );
+        assert!(parse_trace_duration("500us").is_err());
+        assert!(parse_trace_duration("0s").is_err());
+    }
+
+    #[test]
+    fn test_expand_log_file() {
+        let home = Some(Path::new("/home/alice"));
+        assert_eq!(
+            expand_log_file("~/traces/t.json", home).unwrap(),
+            "/home/alice/traces/t.json"
+        );
+        assert_eq!(expand_log_file("/tmp/t.json", None).unwrap(), "/tmp/t.json");
+        assert!(expand_log_file("~/t.json", None).is_err());
+        assert!(expand_log_file("~bob/t.json", home).is_err());
+        assert!(expand_log_file("t.json", home).is_err());
+        assert!(expand_log_file("./traces/t.json", home).is_err());
+        assert!(expand_log_file("/tmp/t.json\nACTIVITIES_ITERATIONS=1", home).is_err());
+        assert!(expand_log_file("/tmp/t.json\r", home).is_err());
+    }
+
+    #[test]
+    fn test_trace_file_path() {
+        for (log_file, expected) in [
+            ("/tmp/t.json", "/tmp/t_12.json"),
+            ("/tmp/t.json.gz", "/tmp/t_12.json.gz"),
+            ("/tmp/x.pt.trace.json", "/tmp/x.pt.trace_12.json"),
+            ("/tmp/t.trace", "/tmp/t_12.trace"),
+            ("/tmp/t", "/tmp/t_12"),
+            ("/tmp/.trace", "/tmp/.trace_12"),
+            ("/tmp/v1.json.d/t.log", "/tmp/v1.json.d/t_12.log"),
+        ] {
+            assert_eq!(trace_file_path(log_file, 12), expected);
+        }
+        for (log_file, expected) in [
+            ("/tmp/t.json", None),
+            ("/tmp/t.json.gz", None),
+            ("/tmp/t.trace", Some("/tmp/t.trace_12")),
+            ("/tmp/t", None),
+            ("/tmp/.trace", None),
+            ("/tmp/v1.json.d/t.log", Some("/tmp/v1.json.d/t.log_12")),
+        ] {
+            assert_eq!(
+                alternate_trace_file_path(log_file, 12).as_deref(),
+                expected,
+                "{}",
+                log_file
+            );
+        }
+    }
+
+    #[test]
+    fn test_request_json_escaping() {
+        let config = GpuTraceConfig {
+            log_file: String::from(r#"/tmp/a "quoted"\dir/\u0041 trace.json"#),
+            trigger_config: GpuTraceTriggerConfig::DurationBased {
+                profile_start_time: 0,
+                duration_ms: 500,
+            },
+            trace_options: GpuTraceOptions::default(),
+        };
+        let kineto_config = config.config().unwrap();
+        let capture_id = r#"id", "pids": [1], "x": "\"#;
+        let request = request_json(&kineto_config, 7, &[1, 2], 3, capture_id);
+        let parsed: Value = serde_json::from_str(&request).unwrap();
+        assert_eq!(parsed["config"], kineto_config.as_str());
+        assert_eq!(parsed["capture_id"], capture_id);
+        assert_eq!(parsed["pids"], json!([1, 2]));
+        assert_eq!(parsed["job_id"], 7);
+        assert_eq!(parsed.as_object().unwrap().len(), 6);
+    }
+
+    #[test]
+    fn test_manifest_json() {
+        let config = GpuTraceConfig {
+            log_file: String::from("/tmp/t.json"),
+            trigger_config: GpuTraceTriggerConfig::IterationBased {
+                profile_start_iteration: 0,
+                profile_start_iteration_roundup: 1,
+                iterations: 10,
+            },
+            trace_options: GpuTraceOptions::default(),
+        };
+        let capture = GpuTraceCapture {
+            capture_id: String::from("abc"),
+            kineto_config: config.config().unwrap() + "\nUPLOAD_TOKEN=hunter2",
+            response: json!({"processesMatched": [7]}),
+            processes: vec![7],
+            trace_files: vec![trace_file_path(&config.log_file, 7)],
+        };
+        let client = DynoClient::new("trainer-01", 1778, None);
+        let manifest = manifest_json(&client, 42, &[0], 3, &config, &capture, "now");
+        assert_eq!(manifest["capture_id"], "abc");
+        assert_eq!(manifest["job_id"], 42);
+        let host = &manifest["captures"][0];
+        assert_eq!(host["host"], "trainer-01:1778");
+        assert_eq!(host["processes"], json!([7]));
+        assert_eq!(host["trace_files"], json!(["/tmp/t_7.json"]));
+        assert_eq!(host["triggered_at"], "now");
+        let kineto_config = host["kineto_config"].as_str().unwrap();
+        assert!(
+            kineto_config.ends_with("\nUPLOAD_TOKEN=<redacted>"),
+            "{}",
+            kineto_config
+        );
+    }
+
     #[test]
     fn test_gputrace_config() {
         let mut test_trace_options = GpuTraceOptions {
@@ -233,7 +1026,7 @@ ACTIVITIES_ITERATIONS=42"#
             with_modules: true,
         };
         assert_eq!(
-            test_trace_options.config(Some(42)),
+            test_trace_options.config(Some(42)).unwrap(),
             r#"
 PROFILE_REPORT_INPUT_SHAPES=true
 PROFILE_WITH_STACK=true
@@ -245,12 +1038,23 @@ PROFILE_WITH_MODULES=true"#
         let test_trace_config = GpuTraceConfig {
             log_file: String::from("/tmp/test_trace.json"),
             trigger_config: GpuTraceTriggerConfig::IterationBased {
+                profile_start_iteration: 0,
                 profile_start_iteration_roundup: 1000,
                 iterations: 42,
             },
             trace_options: test_trace_options,
         };
-        test_trace_config.config();
+        test_trace_config.config().unwrap();
+
+        // Memory profiling needs a duration
+        let test_trace_config = GpuTraceConfig {
+            trace_options: GpuTraceOptions {
+                profile_memory: true,
+                ..Default::default()
+            },
+            ..test_trace_config
+        };
+        assert!(test_trace_config.config().is_err());
 
         // Test duration based config with profile_memory
         test_trace_options = GpuTraceOptions {
@@ -269,7 +1073,7 @@ PROFILE_WITH_MODULES=true"#
             trace_options: test_trace_options,
         };
         assert_eq!(
-            test_trace_config.config(),
+            test_trace_config.config().unwrap(),
             r#"ACTIVITIES_LOG_FILE=/tmp/test_trace.json
 PROFILE_START_TIME=1000
 ACTIVITIES_DURATION_MSECS=42
@@ -0,0 +1,181 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::process::Child;
+use std::process::ChildStdout;
+use std::process::Command;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+
+use anyhow::Context;
+use anyhow::Result;
+
+use super::deadline;
+use super::progress::Progress;
+
+// This module contains --kube-pod, which reaches a dynolog running inside a
+// Kubernetes pod through `kubectl port-forward`. kubectl tunnels through the
+// API server with the user's kubeconfig, so the pod doesn't need to be
+// reachable from the client.
+
+/// A pod given as NAMESPACE/POD, or POD in the current kubectl namespace
+#[derive(Clone, Debug, PartialEq)]
+pub struct KubePod {
+    pub namespace: Option<String>,
+    pub name: String,
+}
+
+pub fn parse_kube_pod(s: &str) -> Result<KubePod> {
+    let (namespace, name) = match s.split_once('/') {
+        Some((namespace, name)) => (Some(namespace.to_string()), name),
+        None => (None, s),
+    };
+    if name.is_empty() || name.contains('/') || namespace.as_deref() == Some("") {
+        return Err(anyhow::anyhow!("expected NAMESPACE/POD or POD"));
+    }
+    Ok(KubePod {
+        namespace,
+        name: name.to_string(),
+    })
+}
+
+/// A running `kubectl port-forward`, stopped when dropped
+pub struct PortForward {
+    child: Arc<Mutex<Child>>,
+    pub local_port: u16,
+}
+
+/// Local port from kubectl's "Forwarding from 127.0.0.1:41234 -> 1778"
+fn parse_forwarding_line(line: &str) -> Option<u16> {
+    let addr = line
+        .strip_prefix("Forwarding from ")?
+        .split(" -> ")
+        .next()?;
+    addr.rsplit_once(':')?.1.parse().ok()
+}
+
+impl PortForward {
+    /// Forward a free local port to `port` of `pod`
+    pub fn start(pod: &KubePod, port: u16) -> Result<Self> {
+        let _progress = Progress::start(&format!("Port-forwarding to pod {}", pod.name));
+        let mut cmd = Command::new("kubectl");
+        cmd.arg("port-forward");
+        if let Some(namespace) = &pod.namespace {
+            cmd.args(["--namespace", namespace]);
+        }
+        // Port 0 lets kubectl pick a free local port
+        cmd.arg(format!("pod/{}", pod.name))
+            .arg(format!(":{}", port))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        tracing::info!("Running {:?}", cmd);
+        let mut child = cmd
+            .spawn()
+            .context("Couldn't run kubectl, is it installed and in PATH?")?;
+
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if stdout.read_line(&mut line)? == 0 {
+                // kubectl exited without forwarding, its stderr says why
+                let mut stderr = String::new();
+                child.stderr.take().unwrap().read_to_string(&mut stderr)?;
+                child.wait()?;
+                return Err(anyhow::anyhow!(
+                    "kubectl port-forward to pod {} failed: {}",
+                    pod.name,
+                    stderr.trim()
+                ));
+            }
+            tracing::debug!("kubectl: {}", line.trim_end());
+            if let Some(local_port) = parse_forwarding_line(line.trim_end()) {
+                tracing::info!("Forwarding 127.0.0.1:{} to pod {}", local_port, pod.name);
+                drain(stdout);
+                let child = Arc::new(Mutex::new(child));
+                let expired_child = child.clone();
+                deadline::on_expiry(move || {
+                    if let Ok(mut child) = expired_child.try_lock() {
+                        let _ = child.kill();
+                    }
+                });
+                return Ok(Self { child, local_port });
+            }
+        }
+    }
+}
+
+/// Address to reach dynolog at: a port forward to `pod` if given, otherwise
+/// `hostname` and `port` unchanged
+pub fn forward_target(
+    pod: Option<KubePod>,
+    hostname: String,
+    port: u16,
+) -> Result<(Option<PortForward>, String, u16)> {
+    match pod {
+        Some(pod) => {
+            let port_forward = PortForward::start(&pod, port)?;
+            let local_port = port_forward.local_port;
+            Ok((Some(port_forward), "127.0.0.1".to_string(), local_port))
+        }
+        None => Ok((None, hostname, port)),
+    }
+}
+
+/// Keep reading kubectl's output, it logs every forwarded connection and
+/// would block once the pipe is full.
+fn drain(stdout: BufReader<ChildStdout>) {
+    thread::spawn(move || {
+        for line in stdout.lines().map_while(Result::ok) {
+            tracing::trace!("kubectl: {}", line);
+        }
+    });
+}
+
+impl Drop for PortForward {
+    fn drop(&mut self) {
+        let mut child = self.child.lock().unwrap();
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_kube_pod() {
+        assert_eq!(
+            parse_kube_pod("ml/trainer-0").unwrap(),
+            KubePod {
+                namespace: Some("ml".to_string()),
+                name: "trainer-0".to_string()
+            }
+        );
+        assert_eq!(parse_kube_pod("trainer-0").unwrap().namespace, None);
+        assert!(parse_kube_pod("ml/").is_err());
+        assert!(parse_kube_pod("/trainer-0").is_err());
+        assert!(parse_kube_pod("a/b/c").is_err());
+
+        assert_eq!(
+            parse_forwarding_line("Forwarding from 127.0.0.1:41234 -> 1778"),
+            Some(41234)
+        );
+        assert_eq!(
+            parse_forwarding_line("Forwarding from [::1]:41234 -> 1778"),
+            Some(41234)
+        );
+        assert_eq!(parse_forwarding_line("Handling connection for 41234"), None);
+    }
+}
@@ -11,8 +11,34 @@
 // handling code. Additionally, explicitly "exporting" all the command modules here allows
 // us to avoid having to explicitly list all the command modules in main.rs.
 
+pub mod atomic_file;
+pub mod bench;
+pub mod bootstrap;
+pub mod config;
 pub mod dcgm;
+pub mod deadline;
+pub mod discovery;
+pub mod doctor;
 pub mod gputrace;
+#[cfg(feature = "kube")]
+pub mod kube;
+pub mod plugin;
+pub mod progress;
+pub mod prompt;
+pub mod redact;
+pub mod render;
+pub mod schedule;
+pub mod script;
+#[cfg(feature = "serve")]
+pub mod serve;
 pub mod status;
+pub mod style;
+pub mod timestamp;
+pub mod trace;
+pub mod trace_diff;
+pub mod trace_merge;
+pub mod trace_summary;
+pub mod utils;
 pub mod version;
+pub mod watch;
 // ... add new command modules here
@@ -0,0 +1,139 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::ffi::OsStr;
+use std::ffi::OsString;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+use anyhow::Context;
+use anyhow::Result;
+use clap::ValueEnum;
+
+use super::render::OutputFormat;
+use super::utils::DynoClient;
+
+// This module contains the handling logic for plugins: like git, `dyno foo`
+// runs a `dyno-foo` executable found on PATH when foo is neither a built-in
+// subcommand nor a config file alias. Plugins get the target dynolog in the
+// same environment variables dyno reads, so a plugin calling `dyno status`
+// talks to the same daemon:
+//
+//   DYNO_HOSTNAME, DYNO_PORT   where dynolog listens, after --kube-pod
+//   DYNO_RPC_TIMEOUT           e.g. 1500ms, unset without --rpc-timeout
+//   DYNO_FORMAT                the requested output format, e.g. json
+//   DYNO_AS                    the operator, see --as
+//   DYNO_BIN                   path of the dyno that ran the plugin
+
+/// The plugin exited with a non-zero status, which dyno exits with as well
+#[derive(Debug)]
+pub struct PluginExit(pub i32);
+
+impl std::fmt::Display for PluginExit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "plugin exited with status {}", self.0)
+    }
+}
+
+impl std::error::Error for PluginExit {}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// Find `dyno-<name>` in the directories of `path`, a PATH style list
+fn find_in(name: &OsStr, path: &OsStr) -> Option<PathBuf> {
+    let mut file_name = OsString::from("dyno-");
+    file_name.push(name);
+    let mut candidates = vec![file_name.clone()];
+    if cfg!(windows) {
+        file_name.push(".exe");
+        candidates.push(file_name);
+    }
+    std::env::split_paths(path)
+        .flat_map(|dir| candidates.iter().map(move |file| dir.join(file)))
+        .find(|candidate| is_executable(candidate))
+}
+
+/// Path of the `dyno-<name>` plugin on PATH
+pub fn find(name: &OsStr) -> Option<PathBuf> {
+    find_in(name, &std::env::var_os("PATH")?)
+}
+
+/// Run the plugin for `args`, the plugin name followed by its arguments,
+/// and wait for it to exit
+pub fn run_plugin(args: &[OsString], client: &DynoClient, format: OutputFormat) -> Result<()> {
+    let name = &args[0];
+    let path = find(name).ok_or_else(|| {
+        anyhow::anyhow!(
+            "'{}' is not a dyno subcommand, and there is no dyno-{} plugin on PATH",
+            name.to_string_lossy(),
+            name.to_string_lossy()
+        )
+    })?;
+    tracing::info!("Running plugin {}", path.display());
+
+    let mut command = Command::new(&path);
+    command
+        .args(&args[1..])
+        .env("DYNO_HOSTNAME", &client.hostname)
+        .env("DYNO_PORT", client.port.to_string())
+        .env_remove("DYNO_RPC_TIMEOUT")
+        .env("DYNO_AS", client.user.as_deref().unwrap_or_default());
+    if let Some(timeout) = client.timeout {
+        command.env("DYNO_RPC_TIMEOUT", format!("{}ms", timeout.as_millis()));
+    }
+    if let Some(value) = format.to_possible_value() {
+        command.env("DYNO_FORMAT", value.get_name());
+    }
+    if let Ok(exe) = std::env::current_exe() {
+        command.env("DYNO_BIN", exe);
+    }
+
+    let status = command
+        .status()
+        .with_context(|| format!("Unable to run {}", path.display()))?;
+    match status.code() {
+        Some(0) => Ok(()),
+        Some(code) => Err(PluginExit(code).into()),
+        // Killed by a signal
+        None => Err(anyhow::anyhow!("{} {}", path.display(), status)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_find_plugin() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("dyno_plugin_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let plugin = dir.join("dyno-hello");
+        std::fs::write(&plugin, "#!/bin/sh\n").unwrap();
+        std::fs::write(dir.join("dyno-notes"), "not executable").unwrap();
+        std::fs::set_permissions(&plugin, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let path = std::env::join_paths(["/nonexistent".as_ref(), dir.as_path()]).unwrap();
+        assert_eq!(find_in("hello".as_ref(), &path), Some(plugin));
+        assert_eq!(find_in("notes".as_ref(), &path), None);
+        assert_eq!(find_in("missing".as_ref(), &path), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,79 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::io::IsTerminal;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// This module contains the spinner shown on stderr while waiting on slow
+// operations, e.g. connecting to a remote host or loading a large trace.
+
+static PROGRESS_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Don't show the spinner before this, so fast operations don't flicker.
+const SHOW_AFTER: Duration = Duration::from_millis(300);
+const TICK: Duration = Duration::from_millis(100);
+const FRAMES: [char; 4] = ['|', '/', '-', '\\'];
+
+/// Disable the spinner, e.g. for --quiet
+pub fn init(enabled: bool) {
+    PROGRESS_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// A spinner that runs until dropped. Nothing is shown when stderr is not a
+/// terminal.
+pub struct Progress {
+    done: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Progress {
+    pub fn start(message: &str) -> Self {
+        let done = Arc::new(AtomicBool::new(false));
+        let handle = (PROGRESS_ENABLED.load(Ordering::Relaxed) && std::io::stderr().is_terminal())
+            .then(|| {
+                let done = done.clone();
+                let message = message.to_string();
+                thread::spawn(move || spin(&message, &done))
+            });
+        Self { done, handle }
+    }
+}
+
+fn spin(message: &str, done: &AtomicBool) {
+    let mut waited = Duration::ZERO;
+    let mut shown = false;
+    let mut frame = 0;
+    while !done.load(Ordering::Relaxed) {
+        if waited >= SHOW_AFTER {
+            eprint!("\r{} {}", FRAMES[frame % FRAMES.len()], message);
+            let _ = std::io::stderr().flush();
+            shown = true;
+            frame += 1;
+        }
+        thread::sleep(TICK);
+        waited += TICK;
+    }
+    if shown {
+        // Clear the spinner line
+        eprint!("\r\x1b[K");
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -0,0 +1,64 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::io::BufRead;
+use std::io::IsTerminal;
+use std::io::Write;
+
+use anyhow::Result;
+
+// This module contains the confirmation prompt for commands that disrupt
+// production behavior, e.g. pausing dcgm profiling.
+
+/// Ask the user to confirm `action`, fails if they decline. `assume_yes`
+/// (--yes) skips the prompt, and is required when stdin is not a terminal.
+pub fn confirm(action: &str, assume_yes: bool) -> Result<()> {
+    if assume_yes {
+        return Ok(());
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(anyhow::anyhow!(
+            "Refusing to {} without confirmation, pass --yes or set DYNO_YES=1 when running non-interactively",
+            action
+        ));
+    }
+    eprint!("{}? [y/N] ", capitalize(action));
+    std::io::stderr().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    if is_yes(&answer) {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("Aborted"))
+    }
+}
+
+fn is_yes(answer: &str) -> bool {
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_yes() {
+        assert!(is_yes("y\n"));
+        assert!(is_yes(" YES \n"));
+        assert!(!is_yes("\n"));
+        assert!(!is_yes("no\n"));
+        assert_eq!(capitalize("pause dcgm"), "Pause dcgm");
+    }
+}
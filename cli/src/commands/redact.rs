@@ -0,0 +1,98 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::sync::RwLock;
+
+use serde_json::Value;
+
+// This module contains the redaction of sensitive values from verbose logs,
+// structured output and capture manifests, so rpc payloads and kineto
+// configs can end up in shared CI logs.
+
+const REDACTED: &str = "<redacted>";
+
+/// Keys are sensitive if they contain one of these, case insensitive.
+const DEFAULT_SENSITIVE_KEYS: [&str; 4] = ["token", "password", "secret", "auth"];
+
+static EXTRA_SENSITIVE_KEYS: RwLock<Vec<String>> = RwLock::new(Vec::new());
+
+/// Also redact keys containing one of `keys`, e.g. from the config file
+pub fn init(keys: Vec<String>) {
+    *EXTRA_SENSITIVE_KEYS.write().unwrap() = keys.into_iter().map(|k| k.to_lowercase()).collect();
+}
+
+fn is_sensitive(key: &str) -> bool {
+    let key = key.to_lowercase();
+    DEFAULT_SENSITIVE_KEYS.iter().any(|k| key.contains(k))
+        || EXTRA_SENSITIVE_KEYS
+            .read()
+            .unwrap()
+            .iter()
+            .any(|k| key.contains(k.as_str()))
+}
+
+/// Redact KEY=VALUE lines, the format of kineto configs
+pub fn redact_lines(text: &str) -> String {
+    text.split('\n')
+        .map(|line| match line.split_once('=') {
+            Some((key, _)) if is_sensitive(key.trim()) => format!("{}={}", key, REDACTED),
+            _ => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Redact sensitive keys of a json value in place, including KEY=VALUE
+/// lines in its strings
+pub fn redact_value(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_sensitive(key) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_value(value);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact_value),
+        Value::String(s) if s.contains('=') => *s = redact_lines(s),
+        _ => {}
+    }
+}
+
+/// Redact an rpc payload. Payloads that are not json are redacted line by line.
+pub fn redact_json(msg: &str) -> String {
+    match serde_json::from_str::<Value>(msg) {
+        Ok(mut value) => {
+            redact_value(&mut value);
+            value.to_string()
+        }
+        Err(_) => redact_lines(msg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        init(vec!["LOG_FILE".to_string()]);
+        assert_eq!(
+            redact_json(
+                r#"{"fn":"setKinetOnDemandRequest","auth_token":"abc","pids":[1],
+                "config":"ACTIVITIES_LOG_FILE=/secret/a.json\nACTIVITIES_DURATION_MSECS=500"}"#
+            ),
+            r#"{"auth_token":"<redacted>","config":"ACTIVITIES_LOG_FILE=<redacted>\nACTIVITIES_DURATION_MSECS=500","fn":"setKinetOnDemandRequest","pids":[1]}"#
+        );
+        assert_eq!(
+            redact_lines("PASSWORD=hunter2\nA=1"),
+            "PASSWORD=<redacted>\nA=1"
+        );
+    }
+}
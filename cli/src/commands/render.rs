@@ -0,0 +1,520 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde_json::Value;
+
+use super::atomic_file::AtomicFile;
+use super::deadline;
+use super::redact;
+
+// This module contains the output formatting shared by all commands. Commands
+// build their result as a json value and hand it here for every format except
+// the free-form text they print themselves.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Free-form human readable text
+    Text,
+    /// Aligned columns
+    Table,
+    Json,
+    /// One compact json document per line, for streaming into jq or a log collector
+    Ndjson,
+    Yaml,
+    /// Comma separated values, multiple tables are separated by a blank line
+    Csv,
+}
+
+impl OutputFormat {
+    /// Whether command output goes through `render` instead of free-form text.
+    pub fn is_structured(self) -> bool {
+        self != OutputFormat::Text
+    }
+}
+
+/// File that structured output is also written to, see --out.
+enum OutFile {
+    /// Moved into place by finish_out if anything was written
+    Atomic { file: AtomicFile, written: bool },
+    /// Written as output is printed, for commands that run until interrupted
+    Stream(File),
+}
+
+static OUT_FILE: Mutex<Option<OutFile>> = Mutex::new(None);
+
+/// Discards the --out file if dyno returns before finish_out, e.g. because
+/// connecting to dynolog failed
+pub struct OutGuard;
+
+impl Drop for OutGuard {
+    fn drop(&mut self) {
+        OUT_FILE.lock().unwrap().take();
+    }
+}
+
+/// Also write structured output to `path`. The file is created up front so a
+/// bad path fails before talking to dynolog. With `stream`, output goes to
+/// `path` as it is printed instead of when the command is done, so commands
+/// that run until interrupted, e.g. --watch, keep what they printed.
+pub fn init_out(path: &Path, stream: bool) -> Result<OutGuard> {
+    let out = if stream {
+        let file =
+            File::create(path).with_context(|| format!("Unable to create {}", path.display()))?;
+        OutFile::Stream(file)
+    } else {
+        let file = AtomicFile::create(path)?;
+        // Remove the partial file if the process is interrupted
+        file.remove_on_interrupt();
+        OutFile::Atomic {
+            file,
+            written: false,
+        }
+    };
+    *OUT_FILE.lock().unwrap() = Some(out);
+    // Remove the partial file if --deadline ends the process
+    deadline::on_expiry(|| {
+        if let Ok(mut file) = OUT_FILE.try_lock() {
+            file.take();
+        }
+    });
+    Ok(OutGuard)
+}
+
+/// Move the --out file into place if the command printed anything, even if
+/// it failed afterwards, e.g. an unhealthy status. Otherwise discard it.
+pub fn finish_out() -> Result<()> {
+    match OUT_FILE.lock().unwrap().take() {
+        Some(OutFile::Atomic {
+            file,
+            written: true,
+        }) => file.commit(),
+        _ => Ok(()),
+    }
+}
+
+/// Dotted paths of the fields to print instead of the whole result, see --field.
+static FIELDS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn init_fields(fields: Vec<String>) {
+    *FIELDS.lock().unwrap() = fields;
+}
+
+/// Look up a dotted path such as "response.processesMatched.0"
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |value, key| match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(items) => key.parse::<usize>().ok().and_then(|i| items.get(i)),
+        _ => None,
+    })
+}
+
+/// Render the requested fields. Paths missing from the result are also looked
+/// up in the daemon response it carries, so e.g. "processesMatched" works for
+/// gputrace. Scalars and arrays of scalars are printed as plain lines so
+/// scripts can use them without a json parser.
+fn render_fields(format: OutputFormat, value: &Value, fields: &[String]) -> Result<String> {
+    let mut out = String::new();
+    for field in fields {
+        let selected = lookup(value, field)
+            .or_else(|| lookup(&value["response"], field))
+            .ok_or_else(|| {
+                let keys = value
+                    .as_object()
+                    .map(|map| map.keys().cloned().collect::<Vec<_>>().join(", "))
+                    .unwrap_or_default();
+                anyhow::anyhow!(
+                    "Field '{}' not found in the result, top level fields are: [{}]",
+                    field,
+                    keys
+                )
+            })?;
+        match selected {
+            Value::Array(items) if !items.iter().any(|v| v.is_object() || v.is_array()) => {
+                for item in items {
+                    out.push_str(&cell(item));
+                    out.push('\n');
+                }
+            }
+            Value::Object(_) | Value::Array(_) => out.push_str(&render(format, selected)?),
+            _ => {
+                out.push_str(&cell(selected));
+                out.push('\n');
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Render a command result for printing, with sensitive values redacted and
+/// only the --field selection if there is one
+fn render_output(format: OutputFormat, value: &Value, fields: &[String]) -> Result<String> {
+    let mut value = value.clone();
+    redact::redact_value(&mut value);
+    if fields.is_empty() {
+        render(format, &value)
+    } else {
+        render_fields(format, &value, fields)
+    }
+}
+
+/// Render and print a command result in the given format
+pub fn print(format: OutputFormat, value: &Value) -> Result<()> {
+    let fields = FIELDS.lock().unwrap().clone();
+    let rendered = render_output(format, value, &fields)?;
+    print!("{}", rendered);
+    match OUT_FILE.lock().unwrap().as_mut() {
+        Some(OutFile::Atomic { file, written }) => {
+            file.write_all(rendered.as_bytes())?;
+            *written = true;
+        }
+        Some(OutFile::Stream(file)) => file.write_all(rendered.as_bytes())?,
+        None => {}
+    }
+    Ok(())
+}
+
+/// Render a command result in the given format. Text falls back to json for
+/// callers without a free-form representation.
+pub fn render(format: OutputFormat, value: &Value) -> Result<String> {
+    Ok(match format {
+        OutputFormat::Text | OutputFormat::Json => serde_json::to_string_pretty(value)? + "\n",
+        OutputFormat::Ndjson => serde_json::to_string(value)? + "\n",
+        OutputFormat::Yaml => serde_yaml::to_string(value)?,
+        OutputFormat::Table => render_table(value),
+        OutputFormat::Csv => render_csv(value),
+    })
+}
+
+/// A block of tabular output.
+#[derive(Debug, PartialEq)]
+struct Section {
+    title: Option<String>,
+    /// A single record shown as key/value pairs rather than as a one row table.
+    is_record: bool,
+    columns: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+fn cell(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(cell).collect::<Vec<_>>().join(","),
+        _ => value.to_string(),
+    }
+}
+
+fn is_table(value: &Value) -> bool {
+    matches!(value, Value::Array(items) if !items.is_empty() && items.iter().all(Value::is_object))
+}
+
+/// Flatten nested objects into dotted keys.
+pub fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{}.{}", prefix, key)
+                };
+                flatten(&key, value, out);
+            }
+        }
+        _ => out.push((
+            if prefix.is_empty() {
+                "value".to_string()
+            } else {
+                prefix.to_string()
+            },
+            cell(value),
+        )),
+    }
+}
+
+fn rows_section(title: Option<String>, items: &[Value]) -> Section {
+    let flattened: Vec<Vec<(String, String)>> = items
+        .iter()
+        .map(|item| {
+            let mut fields = vec![];
+            flatten("", item, &mut fields);
+            fields
+        })
+        .collect();
+    let mut columns: Vec<String> = vec![];
+    for (key, _) in flattened.iter().flatten() {
+        if !columns.contains(key) {
+            columns.push(key.clone());
+        }
+    }
+    let rows = flattened
+        .into_iter()
+        .map(|fields| {
+            columns
+                .iter()
+                .map(|column| {
+                    fields
+                        .iter()
+                        .find(|(key, _)| key == column)
+                        .map(|(_, value)| value.clone())
+                        .unwrap_or_default()
+                })
+                .collect()
+        })
+        .collect();
+    Section {
+        title,
+        is_record: false,
+        columns,
+        rows,
+    }
+}
+
+/// Split a value into tables: top level arrays of objects become their own
+/// section, everything else is flattened into a single record.
+fn sections(value: &Value) -> Vec<Section> {
+    match value {
+        Value::Array(items) => vec![rows_section(None, items)],
+        Value::Object(map) => {
+            let mut record = vec![];
+            let mut tables = vec![];
+            for (key, value) in map {
+                if is_table(value) {
+                    tables.push(rows_section(Some(key.clone()), value.as_array().unwrap()));
+                } else {
+                    flatten(key, value, &mut record);
+                }
+            }
+            let mut sections = vec![];
+            if !record.is_empty() {
+                let (columns, row) = record.into_iter().unzip();
+                sections.push(Section {
+                    title: None,
+                    is_record: true,
+                    columns,
+                    rows: vec![row],
+                });
+            }
+            sections.extend(tables);
+            sections
+        }
+        _ => vec![Section {
+            title: None,
+            is_record: true,
+            columns: vec!["value".to_string()],
+            rows: vec![vec![cell(value)]],
+        }],
+    }
+}
+
+fn render_table(value: &Value) -> String {
+    let mut out = String::new();
+    for (i, section) in sections(value).iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        if let Some(title) = &section.title {
+            out.push_str(&format!("{}:\n", title));
+        }
+        if section.is_record {
+            let width = section.columns.iter().map(|c| c.len()).max().unwrap_or(0);
+            for (column, value) in section.columns.iter().zip(&section.rows[0]) {
+                let line = format!("{:width$}  {}", column, value, width = width);
+                out.push_str(line.trim_end());
+                out.push('\n');
+            }
+            continue;
+        }
+        let widths: Vec<usize> = section
+            .columns
+            .iter()
+            .enumerate()
+            .map(|(i, column)| {
+                section
+                    .rows
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain(std::iter::once(column.len()))
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect();
+        let format_row = |row: &[String]| {
+            row.iter()
+                .zip(&widths)
+                .map(|(value, width)| format!("{:width$}", value, width = width))
+                .collect::<Vec<_>>()
+                .join("  ")
+                .trim_end()
+                .to_string()
+        };
+        out.push_str(&format_row(&section.columns));
+        out.push('\n');
+        for row in &section.rows {
+            out.push_str(&format_row(row));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn render_csv(value: &Value) -> String {
+    let mut out = String::new();
+    for (i, section) in sections(value).iter().enumerate() {
+        if i > 0 {
+            out.push('\n');
+        }
+        let line = |row: &[String]| {
+            row.iter()
+                .map(|value| csv_escape(value))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        out.push_str(&line(&section.columns));
+        out.push('\n');
+        for row in &section.rows {
+            out.push_str(&line(row));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn sample() -> Value {
+        json!({
+            "trace_file": "/tmp/trace.json",
+            "totals": {"kernels": 3, "memcpy": null},
+            "top_kernels": [
+                {"name": "gemm", "count": 2, "total_us": 300.0},
+                {"name": "relu, fused", "count": 1},
+            ],
+        })
+    }
+
+    #[test]
+    fn test_render_output_redacts() {
+        let value = json!({
+            "kineto_config": "ACTIVITIES_DURATION_MSECS=500\nUPLOAD_TOKEN=hunter2",
+            "api_token": "hunter2",
+        });
+        let rendered = render_output(OutputFormat::Json, &value, &[]).unwrap();
+        assert!(!rendered.contains("hunter2"), "{}", rendered);
+        assert!(
+            rendered.contains("ACTIVITIES_DURATION_MSECS=500"),
+            "{}",
+            rendered
+        );
+        let rendered =
+            render_output(OutputFormat::Json, &value, &["kineto_config".to_string()]).unwrap();
+        assert_eq!(
+            rendered,
+            "ACTIVITIES_DURATION_MSECS=500\nUPLOAD_TOKEN=<redacted>\n"
+        );
+    }
+
+    #[test]
+    fn test_render_table() {
+        assert_eq!(
+            render(OutputFormat::Table, &sample()).unwrap(),
+            r#"totals.kernels  3
+totals.memcpy
+trace_file      /tmp/trace.json
+
+top_kernels:
+count  name         total_us
+2      gemm         300.0
+1      relu, fused
+"#
+        );
+    }
+
+    #[test]
+    fn test_render_csv() {
+        assert_eq!(
+            render(OutputFormat::Csv, &sample()).unwrap(),
+            r#"totals.kernels,totals.memcpy,trace_file
+3,,/tmp/trace.json
+
+count,name,total_us
+2,gemm,300.0
+1,"relu, fused",
+"#
+        );
+        assert_eq!(
+            render(OutputFormat::Csv, &json!({"status": 1})).unwrap(),
+            "status\n1\n"
+        );
+    }
+
+    #[test]
+    fn test_render_yaml() {
+        assert_eq!(
+            render(OutputFormat::Yaml, &json!({"version": "0.3.2"})).unwrap(),
+            "version: 0.3.2\n"
+        );
+    }
+
+    #[test]
+    fn test_render_fields() {
+        let fields = |fields: &[&str]| {
+            let fields: Vec<String> = fields.iter().map(|f| f.to_string()).collect();
+            render_fields(OutputFormat::Json, &sample(), &fields)
+        };
+        assert_eq!(
+            fields(&["trace_file", "totals.kernels"]).unwrap(),
+            "/tmp/trace.json\n3\n"
+        );
+        assert_eq!(
+            fields(&["top_kernels.1"]).unwrap(),
+            "{\n  \"count\": 1,\n  \"name\": \"relu, fused\"\n}\n"
+        );
+        assert!(fields(&["totals.missing"]).is_err());
+        assert_eq!(
+            render_fields(
+                OutputFormat::Json,
+                &json!({"response": {"processesMatched": [1, 2]}}),
+                &["processesMatched".to_string()]
+            )
+            .unwrap(),
+            "1\n2\n"
+        );
+    }
+
+    #[test]
+    fn test_render_ndjson() {
+        assert_eq!(
+            render(
+                OutputFormat::Ndjson,
+                &json!({"status": 1, "host": "a:1778"})
+            )
+            .unwrap(),
+            "{\"host\":\"a:1778\",\"status\":1}\n"
+        );
+    }
+}
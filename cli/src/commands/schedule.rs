@@ -0,0 +1,122 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::hash::Hasher;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Local;
+use chrono::Utc;
+use croner::Cron;
+
+use super::style;
+use super::timestamp;
+
+// This module contains the handling logic for dyno schedule, which runs a
+// dyno subcommand on a cron schedule in the foreground, e.g. as a systemd
+// service, instead of a crontab entry wrapping dyno.
+
+/// Parse a standard 5 field cron expression, e.g. "0 */4 * * *"
+pub fn parse_cron(s: &str) -> Result<Cron> {
+    Cron::new(s)
+        .parse()
+        .map_err(|err| anyhow::anyhow!("{}, expected e.g. \"0 */4 * * *\"", err))
+}
+
+/// Next time `cron` fires after `now`, in local time unless `utc` is set
+fn next_run(cron: &Cron, now: DateTime<Utc>, utc: bool) -> Result<DateTime<Utc>> {
+    let next = if utc {
+        cron.find_next_occurrence(&now, false)?
+    } else {
+        cron.find_next_occurrence(&now.with_timezone(&Local), false)?
+            .with_timezone(&Utc)
+    };
+    Ok(next)
+}
+
+/// Random delay of up to `jitter`, so hosts sharing a schedule don't all
+/// fire in the same second
+fn random_delay(jitter: Duration) -> Duration {
+    let max_ms = jitter.as_millis() as u64;
+    if max_ms == 0 {
+        return Duration::ZERO;
+    }
+    let random = RandomState::new().build_hasher().finish();
+    Duration::from_millis(random % (max_ms + 1))
+}
+
+/// Call `run` every time `cron` fires, in local time or in UTC with --utc,
+/// plus up to `jitter`, until it ran `max_runs` times. Failed runs are logged
+/// and don't stop the schedule.
+pub fn run_schedule(
+    cron: &Cron,
+    jitter: Option<Duration>,
+    max_runs: Option<u64>,
+    description: &str,
+    mut run: impl FnMut() -> Result<()>,
+) -> Result<()> {
+    let mut runs = 0;
+    while max_runs.is_none_or(|max_runs| runs < max_runs) {
+        let now = Utc::now();
+        let at =
+            next_run(cron, now, timestamp::is_utc())? + random_delay(jitter.unwrap_or_default());
+        eprintln!("Next run of `{}` at {}", description, timestamp::format(at));
+        if let Ok(wait) = (at - now).to_std() {
+            thread::sleep(wait);
+        }
+
+        eprintln!(
+            "{} Running `{}`",
+            timestamp::format(Utc::now()),
+            description
+        );
+        match run() {
+            Ok(()) => eprintln!(
+                "{} {}",
+                timestamp::format(Utc::now()),
+                style::success("Done")
+            ),
+            Err(err) => eprintln!(
+                "{} {}: {:#}",
+                timestamp::format(Utc::now()),
+                style::failure("Failed"),
+                err
+            ),
+        }
+        runs += 1;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedule() {
+        assert!(parse_cron("0 */4 * * *").is_ok());
+        assert!(parse_cron("every 4 hours").is_err());
+        assert!(parse_cron("61 * * * *").is_err());
+
+        let cron = parse_cron("0 */4 * * *").unwrap();
+        let now = DateTime::parse_from_rfc3339("2024-05-01T13:30:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert_eq!(
+            next_run(&cron, now, true).unwrap().to_rfc3339(),
+            "2024-05-01T16:00:00+00:00"
+        );
+
+        let jitter = Duration::from_secs(30);
+        assert!((0..100).all(|_| random_delay(jitter) <= jitter));
+        assert_eq!(random_delay(Duration::ZERO), Duration::ZERO);
+    }
+}
@@ -0,0 +1,210 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::io::Read;
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::json;
+
+use super::render;
+use super::render::OutputFormat;
+use super::style;
+
+// This module contains the handling logic for dyno script, which runs a file
+// of dyno subcommands, one per line, e.g. a codified capture procedure:
+//
+//   # check the daemon before tracing
+//   status
+//   gputrace --job-id 42 --duration 5s --log-file /tmp/trace.json
+//
+// Blank lines and lines starting with # are ignored. Flags of dyno itself,
+// such as --hostname or --json, are passed once, before `script`.
+//
+// dyno run takes the same steps on the command line, joined with &&.
+
+/// One subcommand of a script
+#[derive(Debug, PartialEq)]
+pub struct Step {
+    /// Line in the script, or position in a dyno run chain
+    pub line: usize,
+    pub text: String,
+    pub words: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct StepReport {
+    line: usize,
+    command: String,
+    /// "ok", "failed", or "skipped" after an earlier failure
+    result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    elapsed_ms: f64,
+}
+
+fn parse_steps(content: &str) -> Result<Vec<Step>> {
+    let mut steps = vec![];
+    for (index, line) in content.lines().enumerate() {
+        let text = line.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+        let words = shlex::split(text)
+            .ok_or_else(|| anyhow::anyhow!("Invalid quoting on line {}: {}", index + 1, text))?;
+        steps.push(Step {
+            line: index + 1,
+            text: text.to_string(),
+            words,
+        });
+    }
+    Ok(steps)
+}
+
+/// Read the steps of the script at `path`, or of stdin for "-". The whole
+/// script is checked before anything runs.
+pub fn read_steps(path: &Path) -> Result<Vec<Step>> {
+    let mut content = String::new();
+    if path == Path::new("-") {
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .context("Unable to read the script from stdin")?;
+    } else {
+        content = std::fs::read_to_string(path)
+            .with_context(|| format!("Unable to read script {}", path.display()))?;
+    }
+    parse_steps(&content)
+}
+
+/// Split "status && gputrace --log-file /tmp/t.json" into its steps
+pub fn chain_steps(chain: &str) -> Result<Vec<Step>> {
+    let words = shlex::split(chain).ok_or_else(|| anyhow::anyhow!("Invalid quoting: {}", chain))?;
+    let steps: Vec<Step> = words
+        .split(|word| word == "&&")
+        .enumerate()
+        .map(|(index, words)| Step {
+            line: index + 1,
+            text: words.join(" "),
+            words: words.to_vec(),
+        })
+        .collect();
+    if let Some(empty) = steps.iter().find(|step| step.words.is_empty()) {
+        return Err(anyhow::anyhow!(
+            "Step {} of '{}' is empty",
+            empty.line,
+            chain
+        ));
+    }
+    Ok(steps)
+}
+
+/// Run `steps` in order with `run_step`, stopping at the first failure
+/// unless `keep_going` is set, then print a report of every step.
+pub fn run_script(
+    steps: &[Step],
+    keep_going: bool,
+    format: OutputFormat,
+    mut run_step: impl FnMut(&Step) -> Result<()>,
+) -> Result<()> {
+    let mut reports = vec![];
+    let mut failed = 0;
+    for step in steps {
+        if failed > 0 && !keep_going {
+            reports.push(StepReport {
+                line: step.line,
+                command: step.text.clone(),
+                result: "skipped",
+                error: None,
+                elapsed_ms: 0.0,
+            });
+            continue;
+        }
+        tracing::info!("Running line {}: {}", step.line, step.text);
+        let start = Instant::now();
+        let result = run_step(step);
+        let elapsed_ms = start.elapsed().as_secs_f64() * 1000.0;
+        let error = result.err().map(|err| format!("{:#}", err));
+        if let Some(err) = &error {
+            failed += 1;
+            eprintln!(
+                "{} line {} `{}`: {}",
+                style::failure("Failed"),
+                step.line,
+                step.text,
+                err
+            );
+        }
+        reports.push(StepReport {
+            line: step.line,
+            command: step.text.clone(),
+            result: if error.is_some() { "failed" } else { "ok" },
+            error,
+            elapsed_ms,
+        });
+    }
+
+    if format.is_structured() {
+        render::print(format, &json!({"ok": failed == 0, "steps": reports}))?;
+    } else {
+        let ran = reports.iter().filter(|r| r.result != "skipped").count();
+        eprintln!("Ran {} of {} steps, {} failed", ran, reports.len(), failed);
+    }
+    if failed > 0 {
+        return Err(anyhow::anyhow!(
+            "{} of {} script steps failed",
+            failed,
+            reports.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_script() {
+        let steps = parse_steps(
+            "# capture\n\nstatus\n  gputrace --log-file '/tmp/my trace.json'\nversion\n",
+        )
+        .unwrap();
+        assert_eq!(steps.len(), 3);
+        assert_eq!(steps[1].line, 4);
+        assert_eq!(
+            steps[1].words,
+            vec!["gputrace", "--log-file", "/tmp/my trace.json"]
+        );
+        assert!(parse_steps("status\ngputrace --log-file '/tmp").is_err());
+        let chain = chain_steps("status && gputrace --log-file '/tmp/my trace.json'").unwrap();
+        assert_eq!(chain[1].words, steps[1].words);
+        assert!(chain_steps("status &&").is_err());
+
+        let fail_gputrace = |step: &Step| match step.words[0].as_str() {
+            "gputrace" => Err(anyhow::anyhow!("no processes")),
+            _ => Ok(()),
+        };
+        let mut ran = vec![];
+        let result = run_script(&steps, false, OutputFormat::Text, |step| {
+            ran.push(step.line);
+            fail_gputrace(step)
+        });
+        assert!(result.is_err());
+        assert_eq!(ran, vec![3, 4]);
+
+        let mut ran = vec![];
+        let result = run_script(&steps, true, OutputFormat::Text, |step| {
+            ran.push(step.line);
+            fail_gputrace(step)
+        });
+        assert!(result.is_err());
+        assert_eq!(ran, vec![3, 4, 5]);
+    }
+}
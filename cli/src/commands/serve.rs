@@ -0,0 +1,291 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::io::Read;
+use std::path::Component;
+use std::path::Path;
+
+use anyhow::Result;
+use serde_json::json;
+use serde_json::Value;
+use tiny_http::Header;
+use tiny_http::Method;
+use tiny_http::Response;
+use tiny_http::Server;
+
+use super::gputrace::GpuTraceCapture;
+use super::gputrace::GpuTraceFlags;
+use super::progress;
+use super::redact;
+use super::status::DaemonStatus;
+use super::utils::DynoClient;
+
+// This module contains dyno serve, a small REST API in front of dynolog so
+// dashboards can query status and trigger traces without the CLI:
+//
+//   GET  /status    same as dyno --json status
+//   GET  /version   same as dyno --json version
+//   POST /gputrace  same as dyno --json gputrace, flags are passed as a json
+//                   object, e.g. {"pids": [1234], "log_file": "/tmp/t.json"}
+//
+// There is no authentication: anyone who can reach the address can trigger
+// traces, and dynolog records the user running dyno serve as the operator.
+// It listens on localhost by default and is meant to sit behind a proxy that
+// authenticates callers. POST bodies must be sent as application/json, which
+// browsers only do after a CORS preflight this server never answers, so web
+// pages can't trigger traces through it. --log-dir is required and keeps the
+// trace files callers ask for in one directory.
+
+/// Larger request bodies are rejected, gputrace flags are far smaller
+const MAX_BODY_BYTES: u64 = 64 << 10;
+
+/// Client error, reported as 400 instead of 502
+#[derive(Debug)]
+struct BadRequest(String);
+
+impl std::fmt::Display for BadRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for BadRequest {}
+
+fn bad_request(msg: impl ToString) -> anyhow::Error {
+    BadRequest(msg.to_string()).into()
+}
+
+/// Whether `log_file`, an absolute path, stays inside `log_dir`
+fn is_inside(log_file: &str, log_dir: &Path) -> bool {
+    let path = Path::new(log_file);
+    !path
+        .components()
+        .any(|component| component == Component::ParentDir)
+        && path.starts_with(log_dir)
+}
+
+fn gputrace(client: &DynoClient, log_dir: &Path, body: &str) -> Result<Value> {
+    // Fields mirror the dyno gputrace flags
+    let flags: GpuTraceFlags = serde_json::from_str(body).map_err(bad_request)?;
+    if flags.log_file.is_empty() {
+        return Err(bad_request("log_file is required"));
+    }
+    let request = flags.build().map_err(bad_request)?;
+    if !is_inside(&request.config.log_file, log_dir) {
+        return Err(bad_request(format!(
+            "log_file must be inside {}",
+            log_dir.display()
+        )));
+    }
+    let capture = GpuTraceCapture::trigger(
+        client,
+        request.job_id,
+        &request.pids,
+        request.process_limit,
+        &request.config,
+        request.capture_id,
+    )?;
+    Ok(capture.to_json(&request.config))
+}
+
+/// Whether a Content-Type header value is json, ignoring parameters such
+/// as charset
+fn is_json(content_type: Option<&str>) -> bool {
+    content_type
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("application/json"))
+}
+
+/// Route a request, returning the http status and json body
+fn handle(
+    client: &DynoClient,
+    log_dir: &Path,
+    method: &Method,
+    url: &str,
+    content_type: Option<&str>,
+    body: &str,
+) -> (u16, Value) {
+    let path = url.split('?').next().unwrap_or_default();
+    if *method == Method::Post && !is_json(content_type) {
+        return (
+            415,
+            json!({"error": "request body must be sent as Content-Type: application/json"}),
+        );
+    }
+    let result = match (method, path) {
+        (Method::Get, "/status") => {
+            DaemonStatus::fetch(client).and_then(|status| Ok(serde_json::to_value(status)?))
+        }
+        (Method::Get, "/version") => client
+            .rpc(r#"{"fn":"getVersion"}"#)
+            .and_then(|resp| Ok(serde_json::from_str(&resp)?)),
+        (Method::Post, "/gputrace") => gputrace(client, log_dir, body),
+        (_, "/status" | "/version" | "/gputrace") => {
+            return (405, json!({"error": "method not allowed"}));
+        }
+        _ => return (404, json!({"error": format!("unknown path {}", path)})),
+    };
+    match result {
+        Ok(value) => (200, value),
+        Err(err) if err.is::<BadRequest>() => (400, json!({"error": err.to_string()})),
+        // dynolog could not be reached or failed
+        Err(err) => (502, json!({"error": format!("{:#}", err)})),
+    }
+}
+
+/// Accept ":8080" as a port on localhost. All interfaces have to be asked
+/// for explicitly, e.g. with 0.0.0.0:8080.
+fn listen_addr(listen: &str) -> String {
+    match listen.strip_prefix(':') {
+        Some(port) => format!("127.0.0.1:{}", port),
+        None => listen.to_string(),
+    }
+}
+
+/// Read the request body, or None if it is larger than MAX_BODY_BYTES
+fn read_body(reader: impl Read) -> std::io::Result<Option<String>> {
+    let mut body = String::new();
+    reader.take(MAX_BODY_BYTES + 1).read_to_string(&mut body)?;
+    Ok((body.len() as u64 <= MAX_BODY_BYTES).then_some(body))
+}
+
+/// Serve the REST API on `listen` until interrupted. Trace files must be
+/// inside `log_dir`.
+pub fn run_serve(client: &DynoClient, listen: &str, log_dir: &Path) -> Result<()> {
+    if !log_dir.is_absolute() {
+        return Err(anyhow::anyhow!(
+            "--log-dir must be an absolute path, trace files are written on the dynolog host"
+        ));
+    }
+    // Nobody watches a spinner in a server
+    progress::init(false);
+    let addr = listen_addr(listen);
+    let server = Server::http(&addr)
+        .map_err(|err| anyhow::anyhow!("Couldn't listen on {}: {}", addr, err))?;
+    eprintln!(
+        "Serving dynolog {}:{} on http://{}",
+        client.hostname, client.port, addr
+    );
+
+    for mut request in server.incoming_requests() {
+        let too_large = || {
+            (
+                413,
+                json!({"error": format!("request body is larger than {} bytes", MAX_BODY_BYTES)}),
+            )
+        };
+        let (code, mut value) = if request
+            .body_length()
+            .is_some_and(|len| len as u64 > MAX_BODY_BYTES)
+        {
+            too_large()
+        } else {
+            let content_type = request
+                .headers()
+                .iter()
+                .find(|header| header.field.equiv("Content-Type"))
+                .map(|header| header.value.to_string());
+            match read_body(request.as_reader()) {
+                Ok(Some(body)) => handle(
+                    client,
+                    log_dir,
+                    request.method(),
+                    request.url(),
+                    content_type.as_deref(),
+                    &body,
+                ),
+                Ok(None) => too_large(),
+                Err(err) => (400, json!({"error": err.to_string()})),
+            }
+        };
+        redact::redact_value(&mut value);
+        tracing::info!("{} {} -> {}", request.method(), request.url(), code);
+        let response = Response::from_string(value.to_string())
+            .with_status_code(code)
+            .with_header(Header::from_bytes("Content-Type", "application/json").unwrap());
+        if let Err(err) = request.respond(response) {
+            tracing::warn!("Failed to send response: {}", err);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON: Option<&str> = Some("application/json");
+
+    #[test]
+    fn test_handle_without_dynolog() {
+        let client = DynoClient::new("localhost", 1778, None);
+        let log_dir = Path::new("/tmp");
+        let post = |body: &str| handle(&client, log_dir, &Method::Post, "/gputrace", JSON, body);
+        assert_eq!(
+            handle(&client, log_dir, &Method::Get, "/metrics", None, "").0,
+            404
+        );
+        assert_eq!(
+            handle(&client, log_dir, &Method::Post, "/status", JSON, "").0,
+            405
+        );
+        assert_eq!(
+            post(r#"{"pids": [1]}"#),
+            (400, json!({"error": "log_file is required"}))
+        );
+        let (code, body) =
+            post(r#"{"log_file": "/tmp/t.json", "iterations": 5, "duration_ms": 100}"#);
+        assert_eq!(code, 400, "{}", body);
+        // Validated like the flags of dyno gputrace
+        let (code, body) =
+            post(r#"{"log_file": "/tmp/t.json", "iterations": 5, "preset": "memory-debug"}"#);
+        assert_eq!(code, 400, "{}", body);
+        assert_eq!(listen_addr(":8080"), "127.0.0.1:8080");
+        assert_eq!(listen_addr("0.0.0.0:8080"), "0.0.0.0:8080");
+
+        let (code, body) = post(r#"{"log_file": "/etc/cron.d/t.json"}"#);
+        assert_eq!(code, 400, "{}", body);
+        let log_dir = Path::new("/var/traces");
+        assert!(is_inside("/var/traces/t.json", log_dir));
+        assert!(!is_inside("/var/traces/../t.json", log_dir));
+        assert!(!is_inside("/var/traces2/t.json", log_dir));
+
+        assert_eq!(read_body("{}".as_bytes()).unwrap().as_deref(), Some("{}"));
+        let large = vec![b' '; MAX_BODY_BYTES as usize + 1];
+        assert_eq!(read_body(large.as_slice()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_content_type() {
+        let client = DynoClient::new("localhost", 1778, None);
+        let body = r#"{"log_file": "/tmp/t.json"}"#;
+        // What a cross-site form or fetch without preflight sends
+        for content_type in [
+            None,
+            Some("text/plain"),
+            Some("application/x-www-form-urlencoded"),
+            Some("multipart/form-data; boundary=x"),
+        ] {
+            let (code, _) = handle(
+                &client,
+                Path::new("/tmp"),
+                &Method::Post,
+                "/gputrace",
+                content_type,
+                body,
+            );
+            assert_eq!(code, 415, "{:?}", content_type);
+        }
+        assert!(is_json(Some("application/json; charset=utf-8")));
+        assert!(is_json(Some("Application/JSON")));
+        // GET requests have no body to check
+        assert_ne!(
+            handle(&client, Path::new("/tmp"), &Method::Get, "/nope", None, "").0,
+            415
+        );
+    }
+}
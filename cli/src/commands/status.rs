@@ -5,22 +5,174 @@
  * LICENSE file in the root directory of this source tree.
  */
 
-use std::net::TcpStream;
+use std::fmt;
+use std::time::Duration;
 
 use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
 
-#[path = "utils.rs"]
-mod utils;
+use super::render;
+use super::render::OutputFormat;
+use super::style;
+use super::utils::DynoClient;
+use super::watch;
 
 // This module contains the handling logic for dyno status
 
-/// Get system info
-pub fn run_status(client: TcpStream) -> Result<()> {
-    utils::send_msg(&client, r#"{"fn":"getStatus"}"#).expect("Error sending message to service");
+/// Response of the getStatus rpc
+#[derive(Debug, Deserialize)]
+struct StatusResponse {
+    status: i64,
+}
+
+/// Response of the getVersion rpc
+#[derive(Debug, Deserialize)]
+struct VersionResponse {
+    version: String,
+}
+
+/// State of a dynolog daemon, as shown by dyno status
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DaemonStatus {
+    pub host: String,
+    pub version: String,
+    /// Status code from getStatus, dynolog reports 0 when the dcgm monitor
+    /// failed to collect its profiling fields.
+    pub status: i64,
+    pub healthy: bool,
+}
+
+fn parse_status(resp: &str) -> Result<i64> {
+    let StatusResponse { status } = serde_json::from_str(resp)
+        .map_err(|_| anyhow::anyhow!("Unexpected getStatus response: {}", resp))?;
+    Ok(status)
+}
+
+impl DaemonStatus {
+    fn from_responses(host: String, status_resp: &str, version_resp: &str) -> Result<Self> {
+        let status = parse_status(status_resp)?;
+        let VersionResponse { version } = serde_json::from_str(version_resp)
+            .map_err(|_| anyhow::anyhow!("Unexpected getVersion response: {}", version_resp))?;
+        Ok(Self {
+            host,
+            version,
+            status,
+            healthy: status == 1,
+        })
+    }
+
+    /// Query the status and version of the daemon behind `client`
+    pub fn fetch(client: &DynoClient) -> Result<Self> {
+        let status_resp = client.rpc(r#"{"fn":"getStatus"}"#)?;
+        let version_resp = client.rpc(r#"{"fn":"getVersion"}"#)?;
+        Self::from_responses(
+            format!("{}:{}", client.hostname, client.port),
+            &status_resp,
+            &version_resp,
+        )
+    }
+
+    pub fn description(&self) -> &'static str {
+        status_description(self.status)
+    }
+
+    /// Fail with the reason when dynolog is not healthy, so health check
+    /// scripts can rely on the exit code.
+    pub fn check_healthy(&self) -> Result<()> {
+        check_healthy(self.status)
+    }
+}
+
+impl fmt::Display for DaemonStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let description = if self.healthy {
+            style::success(self.description())
+        } else {
+            style::failure(self.description())
+        };
+        writeln!(f, "{:10} {}", "Host", self.host)?;
+        writeln!(f, "{:10} {}", "Version", self.version)?;
+        writeln!(f, "{:10} {}", "Status", description)
+    }
+}
+
+fn status_description(status: i64) -> &'static str {
+    match status {
+        1 => "healthy",
+        0 => "unhealthy (dcgm profiling fields are blank)",
+        _ => "unhealthy (unknown status code)",
+    }
+}
+
+fn check_healthy(status: i64) -> Result<()> {
+    if status == 1 {
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!("dynolog is {}", status_description(status)))
+    }
+}
+
+/// Get system info, or keep sampling it every `watch` interval
+pub fn run_status(
+    client: &DynoClient,
+    raw: bool,
+    watch: Option<Duration>,
+    format: OutputFormat,
+) -> Result<()> {
+    if let Some(interval) = watch {
+        return watch::watch(interval, format, || {
+            Ok(serde_json::to_value(DaemonStatus::fetch(client)?)?)
+        });
+    }
+
+    if raw {
+        let resp_str = client.rpc(r#"{"fn":"getStatus"}"#)?;
+        let status = parse_status(&resp_str)?;
+        if format.is_structured() {
+            render::print(format, &serde_json::from_str(&resp_str)?)?;
+        } else {
+            println!("response = {}", resp_str);
+        }
+        return check_healthy(status);
+    }
+
+    let status = DaemonStatus::fetch(client)?;
+    if format.is_structured() {
+        render::print(format, &serde_json::to_value(&status)?)?;
+    } else {
+        print!("{}", status);
+    }
+
+    status.check_healthy()
+}
 
-    let resp_str = utils::get_resp(&client).expect("Unable to decode output bytes");
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    println!("response = {}", resp_str);
+    #[test]
+    fn test_daemon_status() {
+        let status = DaemonStatus::from_responses(
+            "localhost:1778".to_string(),
+            r#"{"status": 0}"#,
+            r#"{"version": "0.3.2"}"#,
+        )
+        .unwrap();
+        assert!(!status.healthy);
+        assert!(status.check_healthy().is_err());
+        assert_eq!(
+            status.to_string(),
+            "Host       localhost:1778\n\
+             Version    0.3.2\n\
+             Status     unhealthy (dcgm profiling fields are blank)\n"
+        );
 
-    Ok(())
+        assert!(DaemonStatus::from_responses(
+            "localhost:1778".to_string(),
+            r#"{"status": "failed"}"#,
+            r#"{"version": "0.3.2"}"#,
+        )
+        .is_err());
+    }
 }
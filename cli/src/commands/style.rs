@@ -0,0 +1,60 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::io::IsTerminal;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+// This module contains the terminal highlighting shared by the text output of
+// all commands. Structured formats are never colored.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Color when stdout is a terminal and NO_COLOR is not set
+    Auto,
+    Always,
+    Never,
+}
+
+static COLOR_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Decide once at startup whether text output is colored
+pub fn init(choice: ColorChoice) {
+    let enabled = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        // See https://no-color.org
+        ColorChoice::Auto => {
+            std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+                && std::io::stdout().is_terminal()
+        }
+    };
+    COLOR_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn paint(ansi_code: &str, text: &str) -> String {
+    if COLOR_ENABLED.load(Ordering::Relaxed) {
+        format!("\x1b[{}m{}\x1b[0m", ansi_code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Green, for operations that succeeded
+pub fn success(text: &str) -> String {
+    paint("32", text)
+}
+
+/// Red, for operations that failed
+pub fn failure(text: &str) -> String {
+    paint("31", text)
+}
+
+/// Yellow, for results that need the user's attention
+pub fn warning(text: &str) -> String {
+    paint("33", text)
+}
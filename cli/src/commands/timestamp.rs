@@ -0,0 +1,99 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Local;
+use chrono::NaiveDateTime;
+use chrono::SecondsFormat;
+use chrono::TimeZone;
+use chrono::Utc;
+
+// This module contains the parsing and rendering of wall clock timestamps.
+// Timestamps are shown as ISO-8601 in local time, or in UTC with --utc.
+
+static USE_UTC: AtomicBool = AtomicBool::new(false);
+
+/// Render timestamps in UTC instead of local time
+pub fn init(utc: bool) {
+    USE_UTC.store(utc, Ordering::Relaxed);
+}
+
+/// Whether wall clock times are in UTC, see --utc
+pub fn is_utc() -> bool {
+    USE_UTC.load(Ordering::Relaxed)
+}
+
+/// Render a timestamp as ISO-8601, e.g. 2024-05-01T14:00:00+02:00
+pub fn format(ts: DateTime<Utc>) -> String {
+    if is_utc() {
+        ts.to_rfc3339_opts(SecondsFormat::Secs, true)
+    } else {
+        ts.with_timezone(&Local)
+            .to_rfc3339_opts(SecondsFormat::Secs, false)
+    }
+}
+
+/// Render milliseconds since the unix epoch as ISO-8601
+pub fn format_ms(ms: u64) -> String {
+    match i64::try_from(ms)
+        .ok()
+        .and_then(DateTime::from_timestamp_millis)
+    {
+        Some(ts) => format(ts),
+        None => ms.to_string(),
+    }
+}
+
+/// Parse milliseconds since the unix epoch, or an ISO-8601 time. Times
+/// without an offset are taken as local time.
+pub fn parse_ms(s: &str) -> Result<u64> {
+    if let Ok(ms) = s.parse::<u64>() {
+        return Ok(ms);
+    }
+    let ts = match DateTime::parse_from_rfc3339(s) {
+        Ok(ts) => ts.with_timezone(&Utc),
+        Err(_) => {
+            let naive = NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S")
+                .or_else(|_| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S"))
+                .map_err(|_| {
+                    anyhow::anyhow!(
+                        "Expected milliseconds since epoch or an ISO-8601 time \
+                         such as 2024-05-01T14:00:00+02:00, got '{}'",
+                        s
+                    )
+                })?;
+            Local
+                .from_local_datetime(&naive)
+                .single()
+                .ok_or_else(|| anyhow::anyhow!("'{}' is ambiguous in the local timezone", s))?
+                .with_timezone(&Utc)
+        }
+    };
+    u64::try_from(ts.timestamp_millis())
+        .map_err(|_| anyhow::anyhow!("'{}' is before the unix epoch", s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ms() {
+        assert_eq!(parse_ms("1714572000000").unwrap(), 1714572000000);
+        assert_eq!(parse_ms("2024-05-01T14:00:00Z").unwrap(), 1714572000000);
+        assert_eq!(
+            parse_ms("2024-05-01T16:00:00.500+02:00").unwrap(),
+            1714572000500
+        );
+        assert!(parse_ms("1969-12-31T00:00:00Z").is_err());
+        assert!(parse_ms("tomorrow").is_err());
+    }
+}
@@ -0,0 +1,428 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Deserializer;
+
+use super::progress::Progress;
+
+// This module contains the parsing and aggregation logic for Chrome trace files
+// written by the PyTorch profiler (kineto). It is shared by the local trace
+// analysis commands and does not talk to dynolog.
+
+/// Kineto category for GPU kernel events.
+const CAT_KERNEL: &str = "kernel";
+/// Kineto categories for GPU memory copy/set events.
+const CAT_MEMCPY: &str = "gpu_memcpy";
+const CAT_MEMSET: &str = "gpu_memset";
+
+/// Deserialize an optional field, treating a value of another type as
+/// missing, e.g. "device": "cuda:0", so one odd event doesn't fail the
+/// whole trace.
+fn lenient<'de, D, T>(deserializer: D) -> std::result::Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    Ok(T::deserialize(value).ok())
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TraceEventArgs {
+    #[serde(default, deserialize_with = "lenient")]
+    pub device: Option<i64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub bytes: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TraceEvent {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub cat: String,
+    #[serde(default)]
+    pub ph: String,
+    /// Start timestamp in microseconds.
+    #[serde(default)]
+    pub ts: f64,
+    /// Duration in microseconds, only set on complete ("X") events.
+    #[serde(default)]
+    pub dur: f64,
+    #[serde(default, deserialize_with = "lenient")]
+    pub args: Option<TraceEventArgs>,
+}
+
+impl TraceEvent {
+    fn is_complete(&self) -> bool {
+        self.ph == "X"
+    }
+
+    fn is_gpu_activity(&self) -> bool {
+        self.is_complete() && matches!(self.cat.as_str(), CAT_KERNEL | CAT_MEMCPY | CAT_MEMSET)
+    }
+
+    fn device(&self) -> Option<i64> {
+        self.args.as_ref().and_then(|args| args.device)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ChromeTrace {
+    #[serde(rename = "traceEvents")]
+    trace_events: Vec<TraceEvent>,
+}
+
+/// Parse the events of a Chrome trace, which is either an object holding
+/// `traceEvents` or a bare event array.
+pub fn parse_trace_events<R: BufRead>(mut reader: R) -> Result<Vec<TraceEvent>> {
+    // Peek at the first token instead of using an untagged enum, which would
+    // buffer the whole (possibly multi-GB) document before picking a variant.
+    let is_array = loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            break false;
+        }
+        match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(pos) => break buf[pos] == b'[',
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    };
+    if is_array {
+        Ok(serde_json::from_reader(reader)?)
+    } else {
+        let trace: ChromeTrace = serde_json::from_reader(reader)?;
+        Ok(trace.trace_events)
+    }
+}
+
+/// Load the events from a Chrome trace json file.
+pub fn load_trace_events(path: &Path) -> Result<Vec<TraceEvent>> {
+    let _progress = Progress::start(&format!("Loading {}", path.display()));
+    let file =
+        File::open(path).with_context(|| format!("Unable to open trace {}", path.display()))?;
+    parse_trace_events(BufReader::new(file))
+        .with_context(|| format!("Unable to parse trace {}", path.display()))
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct KernelStats {
+    pub count: u64,
+    pub total_us: f64,
+}
+
+impl KernelStats {
+    pub fn avg_us(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.total_us / self.count as f64
+        }
+    }
+}
+
+/// Total GPU kernel time per kernel name.
+pub fn kernel_stats(events: &[TraceEvent]) -> HashMap<String, KernelStats> {
+    let mut stats: HashMap<String, KernelStats> = HashMap::new();
+    for event in events
+        .iter()
+        .filter(|e| e.is_complete() && e.cat == CAT_KERNEL)
+    {
+        let entry = stats.entry(event.name.clone()).or_default();
+        entry.count += 1;
+        entry.total_us += event.dur;
+    }
+    stats
+}
+
+/// Kernel stats sorted by descending total time.
+pub fn sorted_kernel_stats(stats: &HashMap<String, KernelStats>) -> Vec<(&String, &KernelStats)> {
+    let mut sorted: Vec<_> = stats.iter().collect();
+    sorted.sort_by(|a, b| b.1.total_us.total_cmp(&a.1.total_us).then(a.0.cmp(b.0)));
+    sorted
+}
+
+/// Change in time spent in one kernel between a base and a new trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KernelDiff {
+    pub name: String,
+    pub base_us: f64,
+    pub new_us: f64,
+}
+
+impl KernelDiff {
+    pub fn delta_us(&self) -> f64 {
+        self.new_us - self.base_us
+    }
+
+    /// Relative change against the base trace, `None` for kernels new in the new trace.
+    pub fn delta_pct(&self) -> Option<f64> {
+        if self.base_us > 0.0 {
+            Some(100.0 * self.delta_us() / self.base_us)
+        } else {
+            None
+        }
+    }
+}
+
+/// Per kernel time differences, sorted by descending absolute time increase.
+pub fn diff_kernel_stats(
+    base: &HashMap<String, KernelStats>,
+    new: &HashMap<String, KernelStats>,
+) -> Vec<KernelDiff> {
+    let mut names: Vec<&String> = base.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+    let mut diffs: Vec<KernelDiff> = names
+        .into_iter()
+        .map(|name| KernelDiff {
+            name: name.clone(),
+            base_us: base.get(name).map_or(0.0, |k| k.total_us),
+            new_us: new.get(name).map_or(0.0, |k| k.total_us),
+        })
+        .collect();
+    diffs.sort_by(|a, b| b.delta_us().total_cmp(&a.delta_us()));
+    diffs
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GpuUtilization {
+    /// Time between the first and last GPU activity on the device, in microseconds.
+    pub span_us: f64,
+    /// Time at least one kernel or memory operation was running, in microseconds.
+    pub busy_us: f64,
+}
+
+impl GpuUtilization {
+    pub fn idle_pct(&self) -> f64 {
+        if self.span_us <= 0.0 {
+            0.0
+        } else {
+            100.0 * (1.0 - self.busy_us / self.span_us)
+        }
+    }
+}
+
+/// Busy and idle time per GPU device, overlapping activity is only counted once.
+pub fn gpu_utilization(events: &[TraceEvent]) -> BTreeMap<i64, GpuUtilization> {
+    let mut intervals: BTreeMap<i64, Vec<(f64, f64)>> = BTreeMap::new();
+    for event in events.iter().filter(|e| e.is_gpu_activity()) {
+        intervals
+            .entry(event.device().unwrap_or(0))
+            .or_default()
+            .push((event.ts, event.ts + event.dur));
+    }
+
+    intervals
+        .into_iter()
+        .map(|(device, mut intervals)| {
+            intervals.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let start = intervals[0].0;
+            let mut end = start;
+            let mut busy_us = 0.0;
+            let mut current: Option<(f64, f64)> = None;
+            for (s, e) in intervals {
+                end = end.max(e);
+                current = match current {
+                    Some((cs, ce)) if s <= ce => Some((cs, ce.max(e))),
+                    Some((cs, ce)) => {
+                        busy_us += ce - cs;
+                        Some((s, e))
+                    }
+                    None => Some((s, e)),
+                };
+            }
+            if let Some((cs, ce)) = current {
+                busy_us += ce - cs;
+            }
+            (
+                device,
+                GpuUtilization {
+                    span_us: end - start,
+                    busy_us,
+                },
+            )
+        })
+        .collect()
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MemcpyStats {
+    pub count: u64,
+    pub bytes: u64,
+}
+
+/// Memory copy volume per copy kind, e.g. "Memcpy HtoD (Pageable -> Device)".
+pub fn memcpy_stats(events: &[TraceEvent]) -> BTreeMap<String, MemcpyStats> {
+    let mut stats: BTreeMap<String, MemcpyStats> = BTreeMap::new();
+    for event in events
+        .iter()
+        .filter(|e| e.is_complete() && e.cat == CAT_MEMCPY)
+    {
+        let entry = stats.entry(event.name.clone()).or_default();
+        entry.count += 1;
+        entry.bytes += event.args.as_ref().and_then(|a| a.bytes).unwrap_or(0);
+    }
+    stats
+}
+
+/// Render a byte count with a binary unit suffix.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.2} {}", value, UNITS[unit])
+    }
+}
+
+/// Max characters of a kernel name printed in the trace-summary and
+/// trace-diff tables.
+pub const MAX_NAME_CHARS: usize = 80;
+
+/// Shorten long (templated) kernel names for tabular output.
+pub fn truncate_name(name: &str, max_chars: usize) -> String {
+    if name.chars().count() <= max_chars {
+        name.to_string()
+    } else {
+        let mut truncated: String = name.chars().take(max_chars.saturating_sub(3)).collect();
+        truncated.push_str("...");
+        truncated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(trace: &str) -> Vec<TraceEvent> {
+        parse_trace_events(trace.as_bytes()).unwrap()
+    }
+
+    const TRACE: &str = r#"{
+  "schemaVersion": 1,
+  "traceEvents": [
+    {"ph": "X", "cat": "cpu_op", "name": "aten::mm", "pid": 1, "tid": 1, "ts": 0, "dur": 500},
+    {"ph": "X", "cat": "kernel", "name": "gemm", "pid": 0, "tid": 7, "ts": 100, "dur": 200,
+     "args": {"device": 0, "stream": 7}},
+    {"ph": "X", "cat": "kernel", "name": "gemm", "pid": 0, "tid": 8, "ts": 250, "dur": 100,
+     "args": {"device": 0, "stream": 8}},
+    {"ph": "X", "cat": "kernel", "name": "relu", "pid": 0, "tid": 7, "ts": 500, "dur": 50,
+     "args": {"device": 0}},
+    {"ph": "X", "cat": "gpu_memcpy", "name": "Memcpy HtoD (Pageable -> Device)", "pid": 1,
+     "tid": 7, "ts": 0, "dur": 10, "args": {"device": 1, "bytes": 2048}},
+    {"ph": "X", "cat": "gpu_memcpy", "name": "Memcpy HtoD (Pageable -> Device)", "pid": 1,
+     "tid": 7, "ts": 90, "dur": 10, "args": {"device": 1, "bytes": 1024}},
+    {"ph": "i", "cat": "kernel", "name": "marker", "ts": 1000, "args": {"device": 0}}
+  ]
+}"#;
+
+    #[test]
+    fn test_kernel_stats() {
+        let events = parse(TRACE);
+        let stats = kernel_stats(&events);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            stats["gemm"],
+            KernelStats {
+                count: 2,
+                total_us: 300.0
+            }
+        );
+        let sorted = sorted_kernel_stats(&stats);
+        assert_eq!(sorted[0].0, "gemm");
+        assert_eq!(sorted[1].0, "relu");
+    }
+
+    #[test]
+    fn test_diff_kernel_stats() {
+        let base = kernel_stats(&parse(TRACE));
+        let new = kernel_stats(&parse(
+            r#"[
+            {"ph": "X", "cat": "kernel", "name": "gemm", "ts": 0, "dur": 450},
+            {"ph": "X", "cat": "kernel", "name": "softmax", "ts": 500, "dur": 20}
+        ]"#,
+        ));
+        let diffs = diff_kernel_stats(&base, &new);
+        let names: Vec<&str> = diffs.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, vec!["gemm", "softmax", "relu"]);
+        assert_eq!(diffs[0].delta_us(), 150.0);
+        assert_eq!(diffs[0].delta_pct(), Some(50.0));
+        assert_eq!(diffs[1].delta_pct(), None);
+        assert_eq!(diffs[2].delta_pct(), Some(-100.0));
+    }
+
+    #[test]
+    fn test_gpu_utilization() {
+        let events = parse(TRACE);
+        let util = gpu_utilization(&events);
+        // Device 0: busy [100, 350) and [500, 550) within [100, 550).
+        assert_eq!(
+            util[&0],
+            GpuUtilization {
+                span_us: 450.0,
+                busy_us: 300.0
+            }
+        );
+        assert!((util[&0].idle_pct() - 100.0 / 3.0).abs() < 1e-9);
+        // Device 1: busy [0, 10) and [90, 100) within [0, 100).
+        assert!((util[&1].idle_pct() - 80.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_memcpy_stats_and_array_trace() {
+        let events = parse(
+            r#"[{"ph": "X", "cat": "gpu_memcpy", "name": "Memcpy DtoH", "ts": 0,
+            "dur": 1, "args": {"bytes": 4096}}]"#,
+        );
+        let stats = memcpy_stats(&events);
+        assert_eq!(
+            stats["Memcpy DtoH"],
+            MemcpyStats {
+                count: 1,
+                bytes: 4096
+            }
+        );
+        assert_eq!(format_bytes(512), "512 B");
+
+        // Fields of an unexpected type are skipped instead of failing the parse
+        let events = parse(
+            r#"[{"ph": "X", "cat": "gpu_memcpy", "name": "Memcpy DtoH", "ts": 0,
+            "dur": 1, "args": {"device": "cuda:0", "bytes": -1}},
+            {"ph": "X", "cat": "kernel", "name": "gemm", "ts": 0, "dur": 1, "args": "none"},
+            {"ph": "X", "cat": "kernel", "name": "relu", "ts": 1, "dur": 1,
+            "args": {"device": 0, "bytes": "2048"}}]"#,
+        );
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].device(), None);
+        assert!(events[0].args.as_ref().unwrap().bytes.is_none());
+        assert!(events[1].args.is_none());
+        assert_eq!(events[2].device(), Some(0));
+        assert!(events[2].args.as_ref().unwrap().bytes.is_none());
+        assert_eq!(format_bytes(4096), "4.00 KiB");
+    }
+}
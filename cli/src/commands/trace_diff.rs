@@ -0,0 +1,109 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::path::Path;
+
+use anyhow::Result;
+use serde_json::json;
+
+use super::render;
+use super::render::OutputFormat;
+use super::style;
+use super::trace;
+
+// This module contains the handling logic for dyno trace-diff
+
+/// Compare kernel time breakdowns of two traces and print kernels that regressed
+pub fn run_trace_diff(
+    base_file: &Path,
+    new_file: &Path,
+    threshold_pct: f64,
+    format: OutputFormat,
+) -> Result<()> {
+    let base = trace::kernel_stats(&trace::load_trace_events(base_file)?);
+    let new = trace::kernel_stats(&trace::load_trace_events(new_file)?);
+
+    let base_total_us: f64 = base.values().map(|k| k.total_us).sum();
+    let new_total_us: f64 = new.values().map(|k| k.total_us).sum();
+    let regressions: Vec<_> = trace::diff_kernel_stats(&base, &new)
+        .into_iter()
+        .filter(|diff| {
+            diff.delta_us() > 0.0 && diff.delta_pct().is_none_or(|pct| pct > threshold_pct)
+        })
+        .collect();
+
+    if format.is_structured() {
+        return render::print(
+            format,
+            &json!({
+                "base_file": base_file,
+                "new_file": new_file,
+                "threshold_pct": threshold_pct,
+                "base_total_kernel_us": base_total_us,
+                "new_total_kernel_us": new_total_us,
+                "regressions": regressions
+                    .iter()
+                    .map(|diff| json!({
+                        "name": diff.name,
+                        "base_us": diff.base_us,
+                        "new_us": diff.new_us,
+                        "delta_us": diff.delta_us(),
+                        "delta_pct": diff.delta_pct(),
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+        );
+    }
+
+    println!("Base: {}", base_file.display());
+    println!("New:  {}", new_file.display());
+    println!(
+        "Total GPU kernel time: {:.3} ms -> {:.3} ms ({:+.3} ms)",
+        base_total_us / 1000.0,
+        new_total_us / 1000.0,
+        (new_total_us - base_total_us) / 1000.0
+    );
+
+    if regressions.is_empty() {
+        println!(
+            "\n{}",
+            style::success(&format!(
+                "No kernel regressed by more than {}%",
+                threshold_pct
+            ))
+        );
+        return Ok(());
+    }
+
+    println!(
+        "\n{}",
+        style::failure(&format!(
+            "{} kernels regressed by more than {}%:",
+            regressions.len(),
+            threshold_pct
+        ))
+    );
+    println!(
+        "    {:>12} {:>12} {:>12} {:>8}  name",
+        "base (ms)", "new (ms)", "delta (ms)", "delta %"
+    );
+    for diff in &regressions {
+        let delta_pct = diff
+            .delta_pct()
+            .map_or_else(|| "new".to_string(), |pct| format!("{:+.1}", pct));
+        println!(
+            "    {:>12.3} {:>12.3} {:>+12.3} {:>8}  {}",
+            diff.base_us / 1000.0,
+            diff.new_us / 1000.0,
+            diff.delta_us() / 1000.0,
+            delta_pct,
+            trace::truncate_name(&diff.name, trace::MAX_NAME_CHARS)
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,262 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use anyhow::Result;
+use serde_json::json;
+use serde_json::Map;
+use serde_json::Value;
+
+use super::atomic_file::AtomicFile;
+use super::progress::Progress;
+use super::render;
+use super::render::OutputFormat;
+
+// This module contains the handling logic for dyno trace-merge, which combines
+// Chrome traces of several hosts or ranks into one timeline. Every input
+// trace gets its own process ids, labelled with the trace it came from, so
+// Perfetto groups the processes of each host together.
+
+/// Kineto writes timestamps relative to this, in ns since epoch
+const BASE_TIME_KEY: &str = "baseTimeNanoseconds";
+
+/// One input trace
+struct TraceInput {
+    path: PathBuf,
+    label: String,
+    base_time_ns: u64,
+    events: Vec<Value>,
+}
+
+/// "host1_1234.pt.trace.json" -> "host1_1234", with the rank if kineto
+/// recorded one
+fn trace_label(path: &Path, doc: &Value) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stem = name
+        .trim_end_matches(".json")
+        .trim_end_matches(".pt.trace")
+        .to_string();
+    match doc["distributedInfo"]["rank"].as_i64() {
+        Some(rank) => format!("{} (rank {})", stem, rank),
+        None => stem,
+    }
+}
+
+fn load_trace(path: &Path) -> Result<TraceInput> {
+    let _progress = Progress::start(&format!("Loading {}", path.display()));
+    let file =
+        File::open(path).with_context(|| format!("Unable to open trace {}", path.display()))?;
+    let doc: Value = serde_json::from_reader(BufReader::new(file))
+        .with_context(|| format!("Unable to parse trace {}", path.display()))?;
+    let label = trace_label(path, &doc);
+    let base_time_ns = doc[BASE_TIME_KEY].as_u64().unwrap_or(0);
+    let events = match doc {
+        Value::Array(events) => events,
+        Value::Object(mut map) => match map.remove("traceEvents") {
+            Some(Value::Array(events)) => events,
+            _ => return Err(anyhow::anyhow!("{} has no traceEvents", path.display())),
+        },
+        _ => return Err(anyhow::anyhow!("{} is not a Chrome trace", path.display())),
+    };
+    Ok(TraceInput {
+        path: path.to_path_buf(),
+        label,
+        base_time_ns,
+        events,
+    })
+}
+
+/// Move the events of `trace` onto the merged timeline and process ids.
+/// `next_pid` is the first pid not used by previous traces.
+fn merge_events(trace: TraceInput, offset_us: f64, next_pid: &mut u64, index: usize) -> Vec<Value> {
+    let mut pids: HashMap<String, u64> = HashMap::new();
+    let mut named: Vec<u64> = vec![];
+    let mut events: Vec<Value> = Vec::with_capacity(trace.events.len());
+    for mut event in trace.events {
+        let Some(obj) = event.as_object_mut() else {
+            continue;
+        };
+        if let Some(pid) = obj.get("pid") {
+            let new_pid = *pids.entry(pid.to_string()).or_insert_with(|| {
+                *next_pid += 1;
+                *next_pid - 1
+            });
+            obj.insert("pid".to_string(), new_pid.into());
+            if obj.get("ph") == Some(&json!("M")) && obj.get("name") == Some(&json!("process_name"))
+            {
+                let name = obj["args"]["name"].as_str().unwrap_or_default().to_string();
+                obj["args"]["name"] = format!("{}: {}", trace.label, name).into();
+                named.push(new_pid);
+            }
+        }
+        if offset_us != 0.0 {
+            if let Some(ts) = obj.get("ts").and_then(Value::as_f64) {
+                obj.insert("ts".to_string(), json!(ts + offset_us));
+            }
+        }
+        // Flow ids are only unique within one trace
+        if let Some(id) = obj
+            .get("id")
+            .filter(|_| matches!(obj.get("ph").and_then(Value::as_str), Some("s" | "t" | "f")))
+        {
+            let id = match id {
+                Value::String(id) => id.clone(),
+                id => id.to_string(),
+            };
+            obj.insert("id".to_string(), format!("{}:{}", index, id).into());
+        }
+        events.push(event);
+    }
+
+    // Name the processes kineto didn't name and keep each trace together
+    let mut new_pids: Vec<u64> = pids.into_values().collect();
+    new_pids.sort();
+    for pid in new_pids {
+        if !named.contains(&pid) {
+            events.push(json!({
+                "ph": "M", "name": "process_name", "pid": pid,
+                "args": {"name": trace.label},
+            }));
+        }
+        events.push(json!({
+            "ph": "M", "name": "process_sort_index", "pid": pid,
+            "args": {"sort_index": pid},
+        }));
+    }
+    events
+}
+
+/// Merge the traces, returning the merged document and per trace details
+fn merge_traces(traces: Vec<TraceInput>) -> (Value, Vec<Value>) {
+    // Traces without a base time have absolute timestamps, i.e. base 0
+    let base_time_ns = traces.iter().map(|t| t.base_time_ns).min().unwrap_or(0);
+    let mut next_pid = 0;
+    let mut events = vec![];
+    let mut summary = vec![];
+    for (index, trace) in traces.into_iter().enumerate() {
+        let offset_us = (trace.base_time_ns - base_time_ns) as f64 / 1000.0;
+        summary.push(json!({
+            "trace_file": trace.path,
+            "label": trace.label,
+            "events": trace.events.len(),
+            "offset_us": offset_us,
+        }));
+        events.extend(merge_events(trace, offset_us, &mut next_pid, index));
+    }
+
+    let mut doc = Map::new();
+    doc.insert("traceEvents".to_string(), events.into());
+    if base_time_ns > 0 {
+        doc.insert(BASE_TIME_KEY.to_string(), base_time_ns.into());
+    }
+    (doc.into(), summary)
+}
+
+/// Merge Chrome traces of several hosts or ranks into `out`
+pub fn run_trace_merge(trace_files: &[PathBuf], out: &Path, format: OutputFormat) -> Result<()> {
+    let traces = trace_files
+        .iter()
+        .map(|path| load_trace(path))
+        .collect::<Result<Vec<_>>>()?;
+    let (merged, summary) = merge_traces(traces);
+
+    {
+        let _progress = Progress::start(&format!("Writing {}", out.display()));
+        let mut file = AtomicFile::create(out)?;
+        serde_json::to_writer(&mut file, &merged)?;
+        file.commit()?;
+    }
+
+    if format.is_structured() {
+        return render::print(format, &json!({"output": out, "traces": summary}));
+    }
+    println!("Merged {} traces into {}", summary.len(), out.display());
+    for trace in &summary {
+        println!(
+            "    {:>12.3} ms  {}",
+            trace["offset_us"].as_f64().unwrap_or_default() / 1000.0,
+            trace["label"].as_str().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trace(name: &str, doc: Value) -> TraceInput {
+        let path = PathBuf::from(name);
+        let label = trace_label(&path, &doc);
+        TraceInput {
+            path,
+            label,
+            base_time_ns: doc[BASE_TIME_KEY].as_u64().unwrap_or(0),
+            events: doc["traceEvents"].as_array().unwrap().clone(),
+        }
+    }
+
+    #[test]
+    fn test_merge_traces() {
+        let host1 = trace(
+            "host1_10.pt.trace.json",
+            json!({
+                "baseTimeNanoseconds": 1_000_000_000u64,
+                "distributedInfo": {"rank": 0},
+                "traceEvents": [
+                    {"ph": "M", "name": "process_name", "pid": 10, "args": {"name": "python"}},
+                    {"ph": "X", "name": "gemm", "pid": 0, "tid": 7, "ts": 5, "dur": 1},
+                    {"ph": "s", "id": 1, "pid": 10, "tid": 10, "ts": 4},
+                ],
+            }),
+        );
+        let host2 = trace(
+            "host2_10.json",
+            json!({
+                "baseTimeNanoseconds": 1_000_002_000u64,
+                "traceEvents": [
+                    {"ph": "X", "name": "gemm", "pid": 10, "tid": 7, "ts": 5, "dur": 1},
+                    {"ph": "s", "id": 1, "pid": 10, "tid": 10, "ts": 4},
+                ],
+            }),
+        );
+        let (merged, summary) = merge_traces(vec![host1, host2]);
+        assert_eq!(merged[BASE_TIME_KEY], 1_000_000_000u64);
+        assert_eq!(summary[1]["offset_us"], 2.0);
+        assert_eq!(summary[0]["label"], "host1_10 (rank 0)");
+
+        let events = merged["traceEvents"].as_array().unwrap();
+        assert_eq!(events[0]["args"]["name"], "host1_10 (rank 0): python");
+        // pid 10 of host2 must not collide with pid 10 of host1
+        let gemm_pids: Vec<&Value> = events
+            .iter()
+            .filter(|e| e["name"] == "gemm")
+            .map(|e| &e["pid"])
+            .collect();
+        assert_eq!(gemm_pids, vec![&json!(1), &json!(2)]);
+        let host2_gemm = events.iter().rfind(|e| e["name"] == "gemm").unwrap();
+        assert_eq!(host2_gemm["ts"], 7.0);
+        let flow_ids: Vec<&Value> = events
+            .iter()
+            .filter(|e| e["ph"] == "s")
+            .map(|e| &e["id"])
+            .collect();
+        assert_eq!(flow_ids, vec![&json!("0:1"), &json!("1:1")]);
+        assert!(events
+            .iter()
+            .any(|e| e["name"] == "process_name" && e["args"]["name"] == "host2_10"));
+    }
+}
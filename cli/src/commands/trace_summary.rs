@@ -0,0 +1,127 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::path::Path;
+
+use anyhow::Result;
+use serde_json::json;
+
+use super::render;
+use super::render::OutputFormat;
+use super::trace;
+
+// This module contains the handling logic for dyno trace-summary
+
+/// Summarize a collected trace file: top GPU kernels, GPU idle time and memcpy volume
+pub fn run_trace_summary(trace_file: &Path, top: usize, format: OutputFormat) -> Result<()> {
+    let events = trace::load_trace_events(trace_file)?;
+
+    let kernels = trace::kernel_stats(&events);
+    let total_kernel_us: f64 = kernels.values().map(|k| k.total_us).sum();
+    let utilization = trace::gpu_utilization(&events);
+    let memcpys = trace::memcpy_stats(&events);
+
+    if format.is_structured() {
+        return render::print(
+            format,
+            &json!({
+                "trace_file": trace_file,
+                "total_kernel_us": total_kernel_us,
+                "top_kernels": trace::sorted_kernel_stats(&kernels)
+                    .into_iter()
+                    .take(top)
+                    .map(|(name, stats)| json!({
+                        "name": name,
+                        "count": stats.count,
+                        "total_us": stats.total_us,
+                        "avg_us": stats.avg_us(),
+                    }))
+                    .collect::<Vec<_>>(),
+                "gpu_utilization": utilization
+                    .iter()
+                    .map(|(device, util)| json!({
+                        "device": device,
+                        "span_us": util.span_us,
+                        "busy_us": util.busy_us,
+                        "idle_pct": util.idle_pct(),
+                    }))
+                    .collect::<Vec<_>>(),
+                "memcpy": memcpys
+                    .iter()
+                    .map(|(kind, stats)| json!({
+                        "kind": kind,
+                        "count": stats.count,
+                        "bytes": stats.bytes,
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+        );
+    }
+
+    println!("Trace: {}", trace_file.display());
+    println!(
+        "GPU kernels: {} launches of {} distinct kernels, {:.3} ms total",
+        kernels.values().map(|k| k.count).sum::<u64>(),
+        kernels.len(),
+        total_kernel_us / 1000.0
+    );
+
+    println!("\nTop {} GPU kernels by total time:", top);
+    println!(
+        "    {:>12} {:>6} {:>8} {:>12}  name",
+        "total (ms)", "%", "count", "avg (us)"
+    );
+    for (name, stats) in trace::sorted_kernel_stats(&kernels).into_iter().take(top) {
+        let pct = if total_kernel_us > 0.0 {
+            100.0 * stats.total_us / total_kernel_us
+        } else {
+            0.0
+        };
+        println!(
+            "    {:>12.3} {:>6.1} {:>8} {:>12.1}  {}",
+            stats.total_us / 1000.0,
+            pct,
+            stats.count,
+            stats.avg_us(),
+            trace::truncate_name(name, trace::MAX_NAME_CHARS)
+        );
+    }
+
+    println!("\nGPU idle time:");
+    if utilization.is_empty() {
+        println!("    No GPU activity found in trace");
+    }
+    for (device, util) in &utilization {
+        println!(
+            "    device {}: {:.1}% idle over {:.3} ms of GPU activity",
+            device,
+            util.idle_pct(),
+            util.span_us / 1000.0
+        );
+    }
+
+    println!("\nMemcpy volume:");
+    if memcpys.is_empty() {
+        println!("    No memcpy found in trace");
+    }
+    for (kind, stats) in &memcpys {
+        println!(
+            "    {:>12} in {:>6} copies  {}",
+            trace::format_bytes(stats.bytes),
+            stats.count,
+            kind
+        );
+    }
+    if memcpys.len() > 1 {
+        println!(
+            "    {:>12} total",
+            trace::format_bytes(memcpys.values().map(|m| m.bytes).sum())
+        );
+    }
+
+    Ok(())
+}
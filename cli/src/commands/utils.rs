@@ -5,32 +5,463 @@
  * LICENSE file in the root directory of this source tree.
  */
 
+use std::borrow::Cow;
+use std::io;
 use std::io::Read;
 use std::io::Write;
+use std::net::SocketAddr;
 use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
 
+use anyhow::Context;
 use anyhow::Result;
+use serde_json::Value;
 
-pub fn send_msg(mut client: &TcpStream, msg: &str) -> Result<()> {
+use super::deadline;
+use super::progress::Progress;
+use super::redact;
+
+/// Exit code when dynolog answered with an error, as opposed to connection
+/// errors (1), invalid flags (2) and an expired --deadline (4)
+pub const EXIT_DAEMON_ERROR: i32 = 3;
+
+/// An error reported by dynolog itself
+#[derive(Debug)]
+pub struct DaemonError(pub String);
+
+impl std::fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for DaemonError {}
+
+/// Detect error replies. dynolog answers requests it can't handle with
+/// {"status": "failed"} or {"status": "failed with exception = ..."}, and
+/// requests that failed, e.g. dcgmProfPause, with {"status": false}. An
+/// "error" key is accepted too.
+fn check_response(resp: &str) -> Result<Value> {
+    let value: Value = serde_json::from_str(resp).map_err(|err| {
+        anyhow::anyhow!(
+            "Invalid response from dynolog, {}: {}",
+            err,
+            hex_preview(resp.as_bytes())
+        )
+    })?;
+    if let Some(error) = value.get("error") {
+        let error = match error {
+            Value::String(error) => error.clone(),
+            error => error.to_string(),
+        };
+        return Err(DaemonError(format!("dynolog reported an error: {}", error)).into());
+    }
+    match value.get("status") {
+        Some(Value::String(status)) if status.starts_with("failed") => {
+            Err(DaemonError(format!("dynolog rejected the request: {}", status)).into())
+        }
+        Some(Value::Bool(false)) => {
+            Err(DaemonError("dynolog reported that the request failed".to_string()).into())
+        }
+        _ => Ok(value),
+    }
+}
+
+/// Address of a dynolog daemon. The daemon serves a single request per
+/// connection, so every rpc opens a new one.
+pub struct DynoClient {
+    pub hostname: String,
+    pub port: u16,
+    /// Limit on connecting and on each read/write, no limit if not set
+    pub timeout: Option<Duration>,
+    /// Larger responses are rejected, see --max-response-mb
+    pub max_response_bytes: u64,
+    /// Operator recorded in every request, see --as
+    pub user: Option<String>,
+}
+
+pub const DEFAULT_MAX_RESPONSE_MB: u64 = 64;
+
+impl DynoClient {
+    pub fn new(hostname: &str, port: u16, timeout: Option<Duration>) -> Self {
+        Self {
+            hostname: hostname.to_string(),
+            port,
+            timeout,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_MB << 20,
+            user: None,
+        }
+    }
+
+    fn connect_addrs(&self, addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+        let timeout = match self.timeout {
+            Some(timeout) => timeout,
+            None => return TcpStream::connect(addrs),
+        };
+        let mut last_err = None;
+        for addr in addrs {
+            match TcpStream::connect_timeout(addr, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = Some(err),
+            }
+        }
+        Err(last_err.unwrap_or_else(|| io::ErrorKind::InvalidInput.into()))
+    }
+
+    /// Create a socket connection to dynolog, trying every resolved address
+    pub fn connect(&self) -> Result<TcpStream> {
+        let addrs: Vec<SocketAddr> = (self.hostname.as_str(), self.port)
+            .to_socket_addrs()
+            .with_context(|| {
+                format!(
+                    "Couldn't resolve hostname '{}', please check --hostname",
+                    self.hostname
+                )
+            })?
+            .collect();
+        if addrs.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Hostname '{}' did not resolve to any address, please check --hostname",
+                self.hostname
+            ));
+        }
+        let tried = addrs
+            .iter()
+            .map(SocketAddr::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        tracing::info!("Connecting to {}:{} at {}", self.hostname, self.port, tried);
+
+        let stream = self.connect_addrs(&addrs).with_context(|| {
+            format!(
+                "Couldn't connect to dynolog on {}:{} (tried {}). Is dynolog running on {}? \
+                 If it listens on a different port try `dyno --port <port>`",
+                self.hostname, self.port, tried, self.hostname
+            )
+        })?;
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+        Ok(stream)
+    }
+
+    /// Explain timeouts, which otherwise surface as EAGAIN
+    fn rpc_context(&self, err: anyhow::Error, what: &str) -> anyhow::Error {
+        let timed_out = err.downcast_ref::<io::Error>().is_some_and(|err| {
+            matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            )
+        });
+        match self.timeout {
+            Some(timeout) if timed_out => err.context(format!(
+                "Timed out after {} {}, see --rpc-timeout",
+                humantime::format_duration(timeout),
+                what
+            )),
+            _ => err.context(format!("Failed {}", what)),
+        }
+    }
+
+    /// Send a request and return the raw response. Error replies of dynolog
+    /// are returned as a `DaemonError`.
+    pub fn rpc(&self, msg: &str) -> Result<String> {
+        let name = serde_json::from_str::<Value>(msg)
+            .ok()
+            .and_then(|request| request["fn"].as_str().map(str::to_string))
+            .unwrap_or_else(|| "rpc".to_string());
+        deadline::rpc_started(&format!("{} on {}:{}", name, self.hostname, self.port));
+        let result = self.send_rpc(msg);
+        deadline::rpc_finished(result.is_ok());
+        result
+    }
+
+    fn send_rpc(&self, msg: &str) -> Result<String> {
+        let _progress = Progress::start(&format!(
+            "Waiting for dynolog on {}:{}",
+            self.hostname, self.port
+        ));
+        let client = self.connect()?;
+        let msg = with_user(msg, self.user.as_deref());
+        tracing::debug!("request = {}", redact::redact_json(&msg));
+        send_msg(&client, &msg)
+            .map_err(|err| self.rpc_context(err, "sending the request to dynolog"))?;
+        let resp = get_resp(&client, self.max_response_bytes)
+            .map_err(|err| self.rpc_context(err, "waiting for dynolog's response"))?;
+        tracing::debug!("response = {}", redact::redact_json(&resp));
+        check_response(&resp)?;
+        Ok(resp)
+    }
+}
+
+/// Add the operator to a request as "user", for dynolog to log who
+/// triggered it. dynolog ignores fields it doesn't know.
+fn with_user<'a>(msg: &'a str, user: Option<&str>) -> Cow<'a, str> {
+    let user = match user {
+        Some(user) => user,
+        None => return Cow::Borrowed(msg),
+    };
+    match serde_json::from_str::<Value>(msg) {
+        Ok(Value::Object(mut request)) => {
+            request.entry("user").or_insert_with(|| Value::from(user));
+            Cow::Owned(Value::Object(request).to_string())
+        }
+        _ => Cow::Borrowed(msg),
+    }
+}
+
+/// The login name of the invoking user, the default of --as
+pub fn login_user() -> Option<String> {
+    ["USER", "USERNAME"]
+        .iter()
+        .find_map(|var| std::env::var(var).ok())
+        .filter(|user| !user.is_empty())
+}
+
+/// Parse a human friendly duration such as "30s", "2m" or "500ms"
+pub fn parse_duration(s: &str) -> Result<Duration> {
+    humantime::parse_duration(s)
+        .map_err(|err| anyhow::anyhow!("{}, expected e.g. 30s, 2m or 500ms", err))
+}
+
+/// Send a length prefixed request. The prefix and the message go out in a
+/// single write, so small requests take one syscall and one packet.
+pub fn send_msg<W: Write>(mut client: W, msg: &str) -> Result<()> {
     let msg_len: [u8; 4] = i32::try_from(msg.len()).unwrap().to_ne_bytes();
 
-    client.write_all(&msg_len)?;
-    client.write_all(msg.as_bytes()).map_err(|err| err.into())
+    let mut buf = Vec::with_capacity(msg_len.len() + msg.len());
+    buf.extend_from_slice(&msg_len);
+    buf.extend_from_slice(msg.as_bytes());
+    client.write_all(&buf)?;
+    client.flush().map_err(|err| err.into())
 }
 
-pub fn get_resp(mut client: &TcpStream) -> Result<String> {
+/// Read a length prefixed response of at most `max_len` bytes
+pub fn get_resp<R: Read>(mut client: R, max_len: u64) -> Result<String> {
     // Response is prefixed with length
     let mut resp_len: [u8; 4] = [0; 4];
-    client.read_exact(&mut resp_len)?;
+    match client.read_exact(&mut resp_len) {
+        Ok(()) => {}
+        // dynolog closes the connection without a reply for unknown rpcs
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+            return Err(DaemonError(
+                "dynolog closed the connection without a response, it doesn't support this request. \
+                 Check that dyno and dynolog versions match with `dyno version`"
+                    .to_string(),
+            )
+            .into());
+        }
+        Err(err) => return Err(err.into()),
+    }
 
+    let prefix = resp_len;
     let resp_len = i32::from_ne_bytes(resp_len);
-    let resp_len = usize::try_from(resp_len).unwrap();
+    let resp_len = u64::try_from(resp_len).map_err(|_| {
+        anyhow::anyhow!(
+            "Invalid response from dynolog, negative length {}, is something else listening on the port?",
+            resp_len
+        )
+    })?;
+
+    tracing::trace!("response length = {}", resp_len);
+    if resp_len > max_len {
+        return Err(anyhow::anyhow!(
+            "dynolog announced a {:.1} MB response, more than the --max-response-mb limit of {} MB: {}",
+            resp_len as f64 / (1 << 20) as f64,
+            max_len >> 20,
+            hex_preview(&prefix)
+        ));
+    }
+
+    // Grow the buffer as data arrives rather than trusting the length up front
+    let mut resp = Vec::new();
+    client.take(resp_len).read_to_end(&mut resp)?;
+    if (resp.len() as u64) < resp_len {
+        return Err(anyhow::anyhow!(
+            "Truncated response from dynolog, got {} of {} bytes: {}",
+            resp.len(),
+            resp_len,
+            hex_preview(&[&prefix[..], &resp].concat())
+        ));
+    }
+
+    String::from_utf8(resp).map_err(|err| {
+        anyhow::anyhow!(
+            "Invalid response from dynolog, not UTF-8: {}",
+            hex_preview(err.as_bytes())
+        )
+    })
+}
+
+/// Hex dump of the first bytes of a malformed response, for bug reports
+fn hex_preview(bytes: &[u8]) -> String {
+    const MAX_BYTES: usize = 32;
+    let shown = &bytes[..bytes.len().min(MAX_BYTES)];
+    let hex = shown
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ");
+    let ascii: String = shown
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+    let more = if bytes.len() > MAX_BYTES { " ..." } else { "" };
+    format!("[{}{}] |{}|", hex, more, ascii)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+
+    /// Counts write calls, i.e. syscalls on a socket
+    #[derive(Default)]
+    struct CountingWriter {
+        writes: usize,
+        data: Vec<u8>,
+    }
+
+    impl Write for CountingWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_send_msg_single_write() {
+        let msg = format!(
+            r#"{{"fn":"setKinetOnDemandRequest","config":"{}"}}"#,
+            "A".repeat(8192)
+        );
+        let mut writer = CountingWriter::default();
+        send_msg(&mut writer, &msg).unwrap();
+        assert_eq!(writer.writes, 1);
+        assert_eq!(get_resp(writer.data.as_slice(), MAX).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_with_user() {
+        let request = r#"{"fn":"dcgmProfPause","duration_s":300}"#;
+        assert_eq!(with_user(request, None), request);
+        assert_eq!(
+            with_user(request, Some("alice")),
+            r#"{"duration_s":300,"fn":"dcgmProfPause","user":"alice"}"#
+        );
+        assert_eq!(
+            with_user(r#"{"fn":"getStatus","user":"bob"}"#, Some("alice")),
+            r#"{"fn":"getStatus","user":"bob"}"#
+        );
+    }
+
+    #[test]
+    fn test_check_response() {
+        assert!(check_response(r#"{"status": 1}"#).is_ok());
+        assert!(check_response(r#"{"status": true}"#).is_ok());
+        for resp in [
+            r#"{"status": false}"#,
+            r#"{"status": "failed"}"#,
+            r#"{"status": "failed with exception = [json.exception.type_error.302]"}"#,
+            r#"{"error": "unknown fn"}"#,
+        ] {
+            assert!(
+                check_response(resp).unwrap_err().is::<DaemonError>(),
+                "{}",
+                resp
+            );
+        }
+        assert!(!check_response("<html>").unwrap_err().is::<DaemonError>());
+
+        let err = get_resp(&[][..], MAX).unwrap_err();
+        assert!(err.is::<DaemonError>(), "{}", err);
+    }
+
+    const MAX: u64 = DEFAULT_MAX_RESPONSE_MB << 20;
 
-    println!("response length = {}", resp_len);
+    fn framed(len: i32, body: &[u8]) -> Vec<u8> {
+        let mut buf = len.to_ne_bytes().to_vec();
+        buf.extend_from_slice(body);
+        buf
+    }
 
-    let mut resp_str = vec![0; resp_len];
+    #[test]
+    fn test_malformed_responses() {
+        let err = get_resp(framed(-1, b"").as_slice(), MAX).unwrap_err();
+        assert!(err.to_string().contains("negative length"), "{}", err);
+        let err = get_resp(framed(100, br#"{"status"#).as_slice(), MAX).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"Truncated response from dynolog, got 8 of 100 bytes: [64 00 00 00 7b 22 73 74 61 74 75 73] |d...{"status|"#
+        );
+        let err = get_resp(framed(3, &[0xff, 0xfe, b'x']).as_slice(), MAX).unwrap_err();
+        assert!(err.to_string().ends_with("[ff fe 78] |..x|"), "{}", err);
+        let err = get_resp(framed(1 << 30, b"{}").as_slice(), MAX).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "dynolog announced a 1024.0 MB response, more than the --max-response-mb \
+             limit of 64 MB: [00 00 00 40] |...@|"
+        );
+        assert!(get_resp(framed(2, b"{}").as_slice(), 2).is_ok());
+        let err = check_response(r#"{"status": 1"#).unwrap_err();
+        assert!(err.to_string().contains("|{\"status\": 1|"), "{}", err);
+    }
 
-    client.read_exact(resp_str.as_mut_slice())?;
+    #[test]
+    fn test_random_responses_dont_panic() {
+        // Small LCG, so failures are reproducible without a rand dependency
+        let mut seed: u64 = 0x2545f4914f6cdd1d;
+        let mut next = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as u32
+        };
+        for _ in 0..2000 {
+            let body: Vec<u8> = (0..next() % 48).map(|_| next() as u8).collect();
+            let buf = match next() % 3 {
+                // Random bytes, including the length prefix
+                0 => body,
+                // Correct length
+                1 => framed(body.len() as i32, &body),
+                // Wrong length
+                _ => framed((next() % 96) as i32 - 16, &body),
+            };
+            if let Ok(resp) = get_resp(buf.as_slice(), MAX) {
+                let _ = check_response(&resp);
+            }
+        }
+    }
 
-    String::from_utf8(resp_str).map_err(|err| err.into())
+    #[test]
+    fn test_connect_error() {
+        // Grab a free port and release it so nothing is listening there.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let err = DynoClient::new("127.0.0.1", port, None)
+            .connect()
+            .unwrap_err();
+        let msg = err.to_string();
+        assert!(
+            msg.contains(&format!("tried 127.0.0.1:{}", port)),
+            "{}",
+            msg
+        );
+        assert!(msg.contains("Is dynolog running"), "{}", msg);
+    }
 }
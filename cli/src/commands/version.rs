@@ -5,22 +5,115 @@
  * LICENSE file in the root directory of this source tree.
  */
 
-use std::net::TcpStream;
-
 use anyhow::Result;
+use chrono::DateTime;
+use serde_json::json;
+use serde_json::Value;
+
+use super::render;
+use super::render::OutputFormat;
+use super::utils::DynoClient;
+
+// This module contains the handling logic for querying dyno version and
+// dyno self version
 
-#[path = "utils.rs"]
-mod utils;
+const CLIENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Set by build.rs
+const CLIENT_COMMIT: &str = env!("DYNO_GIT_COMMIT");
+const CLIENT_BUILD_TIMESTAMP: &str = env!("DYNO_BUILD_TIMESTAMP");
+/// Comma separated cargo features
+const CLIENT_FEATURES: &str = env!("DYNO_FEATURES");
+/// dynolog release this dyno was built with, from version.txt
+pub const DYNOLOG_SOURCE_VERSION: &str = env!("DYNOLOG_SOURCE_VERSION");
 
-// This module contains the handling logic for querying dyno version
+/// The rpcs dyno sends. The wire protocol carries no version of its own, so
+/// compatibility is tied to the dynolog release that serves these.
+const RPCS: &[&str] = &[
+    "getStatus",
+    "getVersion",
+    "setKinetOnDemandRequest",
+    "dcgmProfPause",
+    "dcgmProfResume",
+];
 
-/// Get version info
-pub fn run_version(client: TcpStream) -> Result<()> {
-    utils::send_msg(&client, r#"{"fn":"getVersion"}"#).expect("Error sending message to service");
+fn client_features() -> Vec<&'static str> {
+    CLIENT_FEATURES
+        .split(',')
+        .filter(|feature| !feature.is_empty())
+        .collect()
+}
+
+fn client_build_date() -> String {
+    CLIENT_BUILD_TIMESTAMP
+        .parse()
+        .ok()
+        .and_then(|secs| DateTime::from_timestamp(secs, 0))
+        .map(|ts| ts.format("%Y-%m-%d").to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
 
-    let resp_str = utils::get_resp(&client).expect("Unable to decode output bytes");
+/// Get version info of the CLI and of dynolog. `dyno version --client-only`
+/// is an alias of `dyno self version`.
+pub fn run_version(client: &DynoClient, format: OutputFormat) -> Result<()> {
+    let resp: Value = serde_json::from_str(&client.rpc(r#"{"fn":"getVersion"}"#)?)?;
+    let daemon_version = resp["version"].as_str().unwrap_or("unknown");
+
+    if format.is_structured() {
+        return render::print(
+            format,
+            &json!({
+                "client": {
+                    "version": CLIENT_VERSION,
+                    "commit": CLIENT_COMMIT,
+                    "build_date": client_build_date(),
+                },
+                "version": daemon_version,
+            }),
+        );
+    }
+
+    println!(
+        "dyno {} (commit {}, built {})",
+        CLIENT_VERSION,
+        CLIENT_COMMIT,
+        client_build_date()
+    );
+    println!("dynolog {}", daemon_version);
+
+    Ok(())
+}
 
-    println!("response = {}", resp_str);
+/// Print the build information of the CLI, without connecting to dynolog
+pub fn run_self_version(format: OutputFormat) -> Result<()> {
+    if format.is_structured() {
+        return render::print(
+            format,
+            &json!({
+                "version": CLIENT_VERSION,
+                "commit": CLIENT_COMMIT,
+                "build_date": client_build_date(),
+                "features": client_features(),
+                "protocol": {
+                    "dynolog": DYNOLOG_SOURCE_VERSION,
+                    "rpcs": RPCS,
+                },
+            }),
+        );
+    }
 
+    let features = client_features();
+    println!("dyno {}", CLIENT_VERSION);
+    println!("commit:   {}", CLIENT_COMMIT);
+    println!("built:    {}", client_build_date());
+    println!(
+        "features: {}",
+        if features.is_empty() {
+            "none".to_string()
+        } else {
+            features.join(", ")
+        }
+    );
+    println!("protocol: dynolog {} rpcs", DYNOLOG_SOURCE_VERSION);
+    println!("          {}", RPCS.join(", "));
     Ok(())
 }
@@ -0,0 +1,131 @@
+/*
+ * Copyright (c) Meta Platforms, Inc. and affiliates.
+ *
+ * This source code is licensed under the MIT license found in the
+ * LICENSE file in the root directory of this source tree.
+ */
+
+use std::thread;
+use std::time::Duration;
+
+use anyhow::Result;
+use chrono::Utc;
+use serde_json::json;
+use serde_json::Value;
+
+use super::render;
+use super::render::OutputFormat;
+use super::style;
+use super::timestamp;
+
+// This module contains the sampling loop shared by commands that support
+// --watch. Text output highlights the values that changed since the
+// previous sample, structured formats print every sample as is. A failed
+// sample, e.g. while dynolog restarts, is printed as an error and the loop
+// keeps polling.
+
+/// Print a text sample as key/value lines, highlighting changed values
+fn render_changes(value: &Value, prev: Option<&Value>) -> String {
+    let mut fields = vec![];
+    render::flatten("", value, &mut fields);
+    let mut prev_fields = vec![];
+    if let Some(prev) = prev {
+        render::flatten("", prev, &mut prev_fields);
+    }
+    let width = fields.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+
+    let mut out = format!("--- {} ---\n", timestamp::format(Utc::now()));
+    for (key, value) in &fields {
+        let changed = prev.is_some()
+            && !prev_fields
+                .iter()
+                .any(|(prev_key, prev_value)| prev_key == key && prev_value == value);
+        let value = if changed {
+            style::warning(value)
+        } else {
+            value.clone()
+        };
+        out.push_str(&format!("{:width$}  {}\n", key, value, width = width));
+    }
+    out
+}
+
+/// Print a sample from `sample` every `interval` until interrupted
+pub fn watch<F>(interval: Duration, format: OutputFormat, sample: F) -> Result<()>
+where
+    F: FnMut() -> Result<Value>,
+{
+    watch_samples(interval, format, sample, None)
+}
+
+/// Print `count` samples, or keep sampling if None
+fn watch_samples<F>(
+    interval: Duration,
+    format: OutputFormat,
+    mut sample: F,
+    count: Option<usize>,
+) -> Result<()>
+where
+    F: FnMut() -> Result<Value>,
+{
+    // Changes are highlighted against the last successful sample
+    let mut prev: Option<Value> = None;
+    let mut taken = 0;
+    while count.is_none_or(|count| taken < count) {
+        match sample() {
+            Ok(value) => {
+                if format.is_structured() {
+                    render::print(format, &value)?;
+                } else {
+                    print!("{}", render_changes(&value, prev.as_ref()));
+                }
+                prev = Some(value);
+            }
+            Err(err) if format.is_structured() => {
+                render::print(format, &json!({"error": format!("{:#}", err)}))?;
+            }
+            Err(err) => println!(
+                "--- {} ---\n{}",
+                timestamp::format(Utc::now()),
+                style::failure(&format!("Error: {:#}", err))
+            ),
+        }
+        taken += 1;
+        thread::sleep(interval);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_changes() {
+        let prev = json!({"status": 1, "version": "0.3.2"});
+        let value = json!({"status": 0, "version": "0.3.2"});
+        let out = render_changes(&value, Some(&prev));
+        // Colors are off in tests, only check the layout
+        assert!(out.starts_with("--- "));
+        assert!(out.ends_with("status   0\nversion  0.3.2\n"), "{}", out);
+    }
+
+    #[test]
+    fn test_watch_survives_failed_sample() {
+        let mut calls = 0;
+        let result = watch_samples(
+            Duration::ZERO,
+            OutputFormat::Text,
+            || {
+                calls += 1;
+                match calls {
+                    1 => Err(anyhow::anyhow!("Connection refused")),
+                    _ => Ok(json!({"status": 1})),
+                }
+            },
+            Some(3),
+        );
+        assert!(result.is_ok());
+        assert_eq!(calls, 3);
+    }
+}
@@ -5,18 +5,36 @@
  * LICENSE file in the root directory of this source tree.
  */
 
-use std::net::TcpStream;
-use std::net::ToSocketAddrs;
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::time::Duration;
 
+use anyhow::Context as _;
 use anyhow::Result;
+use chrono::NaiveTime;
+use clap::builder::RangedU64ValueParser;
+use clap::ArgMatches;
+use clap::CommandFactory;
+use clap::ErrorKind;
+use clap::FromArgMatches;
 use clap::Parser;
+use clap::ValueSource;
+use croner::Cron;
 
 // Make all the command modules accessible to this file.
 mod commands;
+use commands::bench::BenchRpc;
+use commands::config::Config;
+use commands::config::ConnectionProfile;
+use commands::config::GpuTraceDefaults;
 use commands::gputrace::GpuTraceCliConfig;
-use commands::gputrace::GpuTraceConfig;
-use commands::gputrace::GpuTraceOptions;
-use commands::gputrace::GpuTraceTriggerConfig;
+use commands::gputrace::GpuTraceFlags;
+use commands::gputrace::GpuTracePreset;
+#[cfg(feature = "kube")]
+use commands::kube::KubePod;
+use commands::render::OutputFormat;
+use commands::style::ColorChoice;
+use commands::utils::DynoClient;
 use commands::*;
 
 // Instructions on adding a new Dyno CLI command:
@@ -38,10 +56,91 @@ const DYNO_PORT: u16 = 1778;
 
 #[derive(Debug, Parser)]
 struct Opts {
-    #[clap(long, default_value = "localhost")]
+    /// Config file with defaults for flags [default: ~/.config/dyno/config.toml]
+    #[clap(long, global = true, env = "DYNO_CONFIG")]
+    config: Option<PathBuf>,
+    /// Named connection profile from the config file
+    #[clap(long, global = true, env = "DYNO_PROFILE")]
+    profile: Option<String>,
+    #[clap(long, env = "DYNO_HOSTNAME", default_value = "localhost")]
     hostname: String,
-    #[clap(long, default_value_t = DYNO_PORT)]
+    #[clap(long, env = "DYNO_PORT", default_value_t = DYNO_PORT)]
     port: u16,
+    /// Operator name sent with every request, so dynolog logs who triggered
+    /// traces and dcgm pauses [default: $USER]
+    #[clap(long = "as", env = "DYNO_AS", value_name = "USER")]
+    user: Option<String>,
+    /// Reach dynolog inside a Kubernetes pod, given as NAMESPACE/POD, through
+    /// `kubectl port-forward` to --port
+    #[cfg(feature = "kube")]
+    #[clap(
+        long,
+        env = "DYNO_KUBE_POD",
+        value_parser = kube::parse_kube_pod,
+        conflicts_with_all = &["hostname", "bootstrap-ssh"]
+    )]
+    kube_pod: Option<KubePod>,
+    /// If nothing listens on --port, start dynolog on --hostname over ssh and
+    /// wait for it before running the command
+    #[clap(long, action)]
+    bootstrap_ssh: bool,
+    /// Command run over ssh by --bootstrap-ssh
+    #[clap(long, default_value = bootstrap::DEFAULT_BOOTSTRAP_CMD)]
+    bootstrap_cmd: String,
+    /// Give up on dynolog rpcs that take longer than this, e.g. 10s [default: no limit]
+    #[clap(long, global = true, env = "DYNO_RPC_TIMEOUT", value_parser = utils::parse_duration)]
+    rpc_timeout: Option<Duration>,
+    /// Abort the whole invocation, including name resolution, connecting,
+    /// every rpc and waits, after this long, e.g. 2m [default: no limit]
+    #[clap(long, global = true, env = "DYNO_DEADLINE", value_parser = utils::parse_duration)]
+    deadline: Option<Duration>,
+    /// Refuse dynolog responses larger than this many MB
+    #[clap(
+        long,
+        global = true,
+        env = "DYNO_MAX_RESPONSE_MB",
+        default_value_t = utils::DEFAULT_MAX_RESPONSE_MB
+    )]
+    max_response_mb: u64,
+    /// Output format for command results
+    #[clap(
+        long,
+        global = true,
+        env = "DYNO_FORMAT",
+        value_enum,
+        default_value_t = OutputFormat::Text
+    )]
+    format: OutputFormat,
+    /// Shorthand for --format json
+    #[clap(long, global = true, action, conflicts_with = "format")]
+    json: bool,
+    /// Only print this dotted path of the command result, e.g. response.processesMatched.
+    /// Can be repeated.
+    #[clap(long = "field", global = true, action = clap::ArgAction::Append)]
+    fields: Vec<String>,
+    /// Also write the command result to this file, requires a structured --format.
+    /// Output of --format ndjson, --watch and schedule is written as it is printed.
+    #[clap(long, global = true)]
+    out: Option<PathBuf>,
+    /// Flush files dyno writes, e.g. --out, to disk before moving them into place
+    #[clap(long, global = true, action)]
+    fsync: bool,
+    /// Log connection details and generated configs, repeat to also log rpc payloads
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// Only log errors. gputrace then only prints the trace file paths, one per line.
+    #[clap(short, long, global = true, action, conflicts_with = "verbose")]
+    quiet: bool,
+    /// Show timestamps in UTC instead of local time
+    #[clap(long, global = true, action)]
+    utc: bool,
+    /// Don't ask for confirmation before disruptive commands such as dcgm-pause,
+    /// required when stdin is not a terminal
+    #[clap(short, long, global = true, env = "DYNO_YES", action, value_parser = clap::builder::BoolishValueParser::new())]
+    yes: bool,
+    /// When to color text output
+    #[clap(long, global = true, value_enum, default_value_t = ColorChoice::Auto)]
+    color: ColorChoice,
     #[clap(subcommand)]
     cmd: Command,
 }
@@ -49,139 +148,1052 @@ struct Opts {
 #[derive(Debug, Parser)]
 enum Command {
     /// Check the status of a dynolog process
-    Status,
-    /// Check the version of a dynolog process
-    Version,
+    Status {
+        /// Print the getStatus response as returned by dynolog
+        #[clap(long)]
+        raw: bool,
+        /// Keep sampling the status, highlighting values that changed
+        #[clap(long, conflicts_with = "raw")]
+        watch: bool,
+        /// Sampling interval of --watch, e.g. 500ms or 10s
+        #[clap(long, value_parser = utils::parse_duration, default_value = "2s")]
+        interval: Duration,
+    },
+    /// Check the version of a dynolog process and of this CLI
+    Version {
+        /// Only print the CLI version, without connecting to dynolog. An alias of
+        /// `dyno self version`.
+        #[clap(long)]
+        client_only: bool,
+    },
     /// Capture gputrace
     Gputrace {
         /// Job id of the application to trace
         #[clap(long, default_value_t = 0)]
         job_id: u64,
-        /// List of pids to capture trace for (comma separated).
-        #[clap(long, default_value = "0")]
-        pids: String,
-        /// Duration of trace to collect in ms.
-        #[clap(long, default_value_t = 500)]
-        duration_ms: u64,
-        /// Training iterations to collect, this takes precedence over duration.
-        #[clap(long, default_value_t = -1)]
-        iterations: i64,
-        /// Log file for trace.
+        /// List of pids to capture trace for (comma separated), 0 matches all processes.
+        #[clap(
+            long,
+            value_delimiter = ',',
+            default_value = "0",
+            value_parser = gputrace::parse_pid
+        )]
+        pids: Vec<u32>,
+        /// Duration of trace to collect in ms [default: 500].
         #[clap(long)]
+        duration_ms: Option<u64>,
+        /// Duration of trace to collect, e.g. 30s, 2m or 500ms. Alternative to --duration-ms.
+        #[clap(long, value_parser = gputrace::parse_trace_duration, conflicts_with = "duration-ms")]
+        duration: Option<Duration>,
+        /// Training iterations to collect, cannot be combined with --duration-ms.
+        #[clap(long)]
+        iterations: Option<i64>,
+        /// Log file for trace, an absolute path on the dynolog host. A leading ~
+        /// expands to your home directory.
+        #[clap(long, value_parser = gputrace::parse_log_file)]
         log_file: String,
-        /// Unix timestamp used for synchronized collection (milliseconds since epoch)
-        #[clap(long, default_value_t = 0)]
-        profile_start_time: u64,
+        /// Start time used for synchronized collection, as milliseconds since epoch
+        /// or an ISO-8601 time such as 2024-05-01T14:00:00+02:00 (local time if
+        /// no offset is given)
+        #[clap(long, value_parser = timestamp::parse_ms)]
+        profile_start_time: Option<u64>,
+        /// Training iteration to start an iteration based trace at. Cannot be
+        /// combined with --profile-start-iteration-roundup.
+        #[clap(long)]
+        profile_start_iteration: Option<u64>,
         /// Start iteration roundup, starts an iteration based trace at a multiple
-        /// of this value.
-        #[clap(long, default_value_t = 1)]
-        profile_start_iteration_roundup: u64,
+        /// of this value [default: 1].
+        #[clap(long)]
+        profile_start_iteration_roundup: Option<u64>,
         /// Max number of processes to profile
         #[clap(long, default_value_t = 3)]
         process_limit: u32,
+        /// Bundle of trace options to start from. Individual trace option flags,
+        /// e.g. --with-stacks=false, override the preset.
+        #[clap(long, value_enum)]
+        preset: Option<GpuTracePreset>,
         /// Record PyTorch operator input shapes and types
-        #[clap(long, action)]
-        record_shapes: bool,
+        #[clap(
+            long,
+            min_values = 0,
+            max_values = 1,
+            require_equals = true,
+            default_missing_value = "true"
+        )]
+        record_shapes: Option<bool>,
         /// Profile PyTorch memory
-        #[clap(long, action)]
-        profile_memory: bool,
+        #[clap(
+            long,
+            min_values = 0,
+            max_values = 1,
+            require_equals = true,
+            default_missing_value = "true"
+        )]
+        profile_memory: Option<bool>,
         /// Capture Python stacks in traces
-        #[clap(long, action)]
-        with_stacks: bool,
+        #[clap(
+            long,
+            min_values = 0,
+            max_values = 1,
+            require_equals = true,
+            default_missing_value = "true"
+        )]
+        with_stacks: Option<bool>,
         /// Annotate operators with analytical flops
-        #[clap(long, action)]
-        with_flops: bool,
+        #[clap(
+            long,
+            min_values = 0,
+            max_values = 1,
+            require_equals = true,
+            default_missing_value = "true"
+        )]
+        with_flops: Option<bool>,
         /// Capture PyTorch operator modules in traces
-        #[clap(long, action)]
-        with_modules: bool,
+        #[clap(
+            long,
+            min_values = 0,
+            max_values = 1,
+            require_equals = true,
+            default_missing_value = "true"
+        )]
+        with_modules: Option<bool>,
         /// Returns exit code 1 if no process is found
         #[clap(long, action)]
         fail_on_no_process: bool,
+        /// Identifier to attach to this capture, a unique one is generated if not set
+        #[clap(long)]
+        capture_id: Option<String>,
+        /// Also write a json manifest of the capture to this file: host, matched processes,
+        /// kineto config, timestamps, capture id and trace file paths
+        #[clap(long)]
+        manifest: Option<PathBuf>,
     },
     /// Pause dcgm profiling. This enables running tools like Nsight compute and avoids conflicts.
     DcgmPause {
         /// Duration to pause dcgm profiling in seconds
         #[clap(long, default_value_t = 300)]
         duration_s: i32,
+        /// Duration to pause dcgm profiling, e.g. 30s or 2m. Alternative to --duration-s.
+        #[clap(long, value_parser = utils::parse_duration, conflicts_with = "duration-s")]
+        duration: Option<Duration>,
+        /// Pause until this local time of day (HH:MM or HH:MM:SS) instead of for --duration-s
+        #[clap(
+            long,
+            value_parser = dcgm::parse_wall_clock,
+            conflicts_with_all = &["duration-s", "duration"]
+        )]
+        until: Option<NaiveTime>,
     },
     /// Resume dcgm profiling
     DcgmResume,
+    /// Check the config file and the connection to dynolog, printing a pass/fail report
+    Doctor,
+    /// Measure rpc throughput and latency of dynolog
+    Bench {
+        /// Total number of rpcs to send
+        #[clap(
+            long,
+            default_value_t = 1000,
+            value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        requests: usize,
+        /// Number of rpcs in flight at once
+        #[clap(
+            long,
+            default_value_t = 8,
+            value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+        )]
+        concurrency: usize,
+        /// Rpc to send, only read-only rpcs are supported
+        #[clap(long, value_enum, default_value_t = BenchRpc::GetVersion)]
+        rpc: BenchRpc,
+    },
+    /// Serve status, version and gputrace of dynolog as a REST API, see commands/serve.rs
+    #[cfg(feature = "serve")]
+    Serve {
+        /// Address to listen on, :8080 is short for 127.0.0.1:8080. There is no
+        /// authentication, only listen on other interfaces behind an authenticating proxy.
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        listen: String,
+        /// Only accept log_file paths inside this absolute directory on the dynolog host
+        #[clap(long)]
+        log_dir: PathBuf,
+    },
+    /// Summarize a collected trace file locally: top GPU kernels, GPU idle time and memcpy volume
+    TraceSummary {
+        /// Chrome trace json file written by the PyTorch profiler
+        trace_file: PathBuf,
+        /// Number of kernels to list
+        #[clap(long, default_value_t = 10)]
+        top: usize,
+    },
+    /// Compare GPU kernel time between two trace files and print the kernels that regressed
+    TraceDiff {
+        /// Baseline Chrome trace json file
+        base_file: PathBuf,
+        /// Chrome trace json file to compare against the baseline
+        new_file: PathBuf,
+        /// Only report kernels whose total time grew by more than this percentage
+        #[clap(long, default_value_t = 5.0)]
+        threshold_pct: f64,
+    },
+    /// Merge Chrome trace files of several hosts or ranks into one, with one process
+    /// group per input trace and timestamps aligned, e.g. for Perfetto
+    TraceMerge {
+        /// File to write the merged trace to
+        #[clap(short, long)]
+        output: PathBuf,
+        /// Chrome trace json files to merge
+        #[clap(required = true, min_values = 2)]
+        trace_files: Vec<PathBuf>,
+    },
+    /// Run the dyno subcommands in a file, one per line, and print a report of every step
+    Script {
+        /// Script file, or - to read it from stdin
+        file: PathBuf,
+        /// Run the remaining steps after a step failed
+        #[clap(long, action)]
+        keep_going: bool,
+    },
+    /// Run a subcommand on a cron schedule until interrupted, e.g.
+    /// dyno schedule --cron "0 */4 * * *" gputrace --log-file /tmp/trace.json
+    #[clap(trailing_var_arg = true)]
+    Schedule {
+        /// 5 field cron expression, in local time or in UTC with --utc
+        #[clap(long, value_parser = schedule::parse_cron)]
+        cron: Cron,
+        /// Delay every run by a random duration of up to this, e.g. 5m
+        #[clap(long, value_parser = utils::parse_duration)]
+        jitter: Option<Duration>,
+        /// Stop after this many runs
+        #[clap(long)]
+        max_runs: Option<u64>,
+        /// Subcommand to run and its flags
+        #[clap(required = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+    /// Run subcommands joined with &&, e.g. "status && gputrace --log-file /tmp/t.json",
+    /// stopping at the first failure
+    Run {
+        /// Subcommands and their flags, separated by &&
+        chain: String,
+    },
+    /// Information about dyno itself
+    #[clap(name = "self", subcommand)]
+    DynoSelf(SelfCommand),
+    /// Any other subcommand runs the dyno-<name> plugin on PATH, see commands/plugin.rs
+    #[clap(external_subcommand)]
+    Plugin(Vec<OsString>),
+}
+
+#[derive(Debug, Parser)]
+enum SelfCommand {
+    /// Print the version, commit, build date, cargo features and dynolog rpcs
+    /// of this build, without connecting to dynolog
+    Version,
+}
+
+impl Command {
+    /// Whether the command keeps printing until it is interrupted
+    fn runs_until_interrupted(&self) -> bool {
+        matches!(
+            self,
+            Command::Status { watch: true, .. } | Command::Schedule { .. }
+        )
+    }
+
+    /// Whether the command works on local files only, without dynolog
+    fn is_local(&self) -> bool {
+        match self {
+            Command::Version { client_only } => *client_only,
+            Command::TraceSummary { .. }
+            | Command::TraceDiff { .. }
+            | Command::TraceMerge { .. }
+            | Command::DynoSelf(_) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Send logs to stderr so they never mix with the command output
+fn init_logging(verbose: u8, quiet: bool) {
+    let level = match (quiet, verbose) {
+        (true, _) => tracing::Level::ERROR,
+        (false, 0) => tracing::Level::WARN,
+        (false, 1) => tracing::Level::INFO,
+        (false, 2) => tracing::Level::DEBUG,
+        (false, _) => tracing::Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_writer(std::io::stderr)
+        .without_time()
+        .with_target(false)
+        .init();
+}
+
+/// Whether a flag was left to its default, so the config file may set it
+fn is_unset(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        None | Some(ValueSource::DefaultValue)
+    )
+}
+
+/// Whether a connection flag set from `source` is replaced by the config
+/// file. A value the selected --profile sets outranks the environment, e.g.
+/// DYNO_HOSTNAME, since choosing a profile is the more specific request.
+fn config_overrides(source: Option<ValueSource>, in_profile: bool) -> bool {
+    match source {
+        None | Some(ValueSource::DefaultValue) => true,
+        Some(ValueSource::EnvVariable) => in_profile,
+        _ => false,
+    }
+}
+
+/// Whether the connection flag `id` may be taken from the config file, see
+/// `config_overrides`
+fn is_unset_for_profile(matches: &ArgMatches, id: &str, in_profile: bool) -> bool {
+    let source = matches.value_source(id);
+    if in_profile && source == Some(ValueSource::EnvVariable) {
+        tracing::warn!(
+            "Ignoring {} in favor of the {} of the selected profile",
+            format!("DYNO_{}", id.to_uppercase().replace('-', "_")),
+            id
+        );
+    }
+    config_overrides(source, in_profile)
+}
+
+/// Fill the flags that were not set explicitly from the user config file and
+/// the selected `profile`
+fn apply_config(
+    opts: &mut Opts,
+    matches: &ArgMatches,
+    config: &Config,
+    profile: Option<&ConnectionProfile>,
+) -> Result<()> {
+    if let Some(hostname) = config.hostname.clone().filter(|_| {
+        is_unset_for_profile(
+            matches,
+            "hostname",
+            profile.is_some_and(|p| p.hostname.is_some()),
+        )
+    }) {
+        opts.hostname = hostname;
+    }
+    if let Some(port) = config.port.filter(|_| {
+        is_unset_for_profile(matches, "port", profile.is_some_and(|p| p.port.is_some()))
+    }) {
+        opts.port = port;
+    }
+    if let Some(timeout) = config.rpc_timeout.as_ref().filter(|_| {
+        is_unset_for_profile(
+            matches,
+            "rpc-timeout",
+            profile.is_some_and(|p| p.rpc_timeout.is_some()),
+        )
+    }) {
+        opts.rpc_timeout = Some(utils::parse_duration(timeout)?);
+    }
+    if let Some(format) = config.format.filter(|_| is_unset(matches, "format")) {
+        opts.format = format;
+    }
+    if let Some(user) = config.user.clone().filter(|_| is_unset(matches, "user")) {
+        opts.user = Some(user);
+    }
+    apply_gputrace_defaults(&mut opts.cmd, matches, &config.gputrace);
+    Ok(())
+}
+
+/// Fill the gputrace flags that were not set explicitly from the [gputrace]
+/// table of the config file. Options resolve from the config file, then
+/// --preset, then the individual flags, so an explicit --preset replaces both
+/// the preset and the per-option defaults of the config file.
+fn apply_gputrace_defaults(cmd: &mut Command, matches: &ArgMatches, defaults: &GpuTraceDefaults) {
+    if let (
+        Command::Gputrace {
+            duration_ms,
+            duration,
+            iterations,
+            process_limit,
+            preset,
+            record_shapes,
+            profile_memory,
+            with_stacks,
+            with_flops,
+            with_modules,
+            ..
+        },
+        Some(("gputrace", gputrace_matches)),
+    ) = (cmd, matches.subcommand())
+    {
+        // A default duration must not clash with an explicitly chosen trigger
+        if duration.is_none() && iterations.is_none() {
+            *duration_ms = duration_ms.or(defaults.duration_ms);
+        }
+        if let Some(limit) = defaults
+            .process_limit
+            .filter(|_| is_unset(gputrace_matches, "process-limit"))
+        {
+            *process_limit = limit;
+        }
+        if preset.is_some() {
+            return;
+        }
+        *preset = defaults.preset;
+        *record_shapes = record_shapes.or(defaults.record_shapes);
+        *profile_memory = profile_memory.or(defaults.profile_memory);
+        *with_stacks = with_stacks.or(defaults.with_stacks);
+        *with_flops = with_flops.or(defaults.with_flops);
+        *with_modules = with_modules.or(defaults.with_modules);
+    }
+}
+
+/// Parse one step of a dyno script: a subcommand or alias with its flags.
+/// Flags of dyno itself, e.g. --hostname or --json, apply to the whole
+/// script and are rejected here.
+fn parse_script_step(
+    command: &clap::Command,
+    words: &[String],
+    config: &Config,
+) -> Result<Command> {
+    let args: Vec<OsString> = std::iter::once(OsString::from("dyno"))
+        .chain(words.iter().map(OsString::from))
+        .collect();
+    let args = expand_alias(command, args, config)?;
+    let matches = command.clone().try_get_matches_from(&args)?;
+    if let Some(arg) = command.get_arguments().find(|arg| {
+        !matches!(arg.get_id(), "help" | "version")
+            && matches.value_source(arg.get_id()) == Some(ValueSource::CommandLine)
+    }) {
+        return Err(anyhow::anyhow!(
+            "--{} applies to the whole script, please pass it before `script`",
+            arg.get_long().unwrap_or_default()
+        ));
+    }
+    let mut opts = Opts::from_arg_matches(&matches)?;
+    if matches!(
+        opts.cmd,
+        Command::Script { .. } | Command::Run { .. } | Command::Schedule { .. }
+    ) {
+        return Err(anyhow::anyhow!(
+            "script, run and schedule can't run each other"
+        ));
+    }
+    if let Command::Plugin(args) = &opts.cmd {
+        if plugin::find(&args[0]).is_none() {
+            return Err(anyhow::anyhow!(
+                "'{}' is not a dyno subcommand, alias or plugin",
+                args[0].to_string_lossy()
+            ));
+        }
+    }
+    apply_gputrace_defaults(&mut opts.cmd, &matches, &config.gputrace);
+    Ok(opts.cmd)
 }
 
-/// Create a socket connection to dynolog
-fn create_dyno_client(host: &str, port: u16) -> Result<TcpStream> {
-    let addr = (host, port)
-        .to_socket_addrs()?
-        .next()
-        .expect("Failed to connect to the server");
+/// Whether `--<long>` is a flag that takes a separate value
+fn takes_value(command: &clap::Command, long: &str) -> bool {
+    command
+        .get_arguments()
+        .any(|arg| arg.get_long() == Some(long) && arg.is_takes_value_set())
+}
 
-    TcpStream::connect(addr).map_err(|err| err.into())
+/// Find --config before clap parses the arguments, since the config file can
+/// define aliases that change them.
+fn config_path(args: &[OsString]) -> Option<PathBuf> {
+    let mut args = args.iter().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--") => break,
+            Some("--config") => return args.next().map(PathBuf::from),
+            Some(arg) if arg.starts_with("--config=") => {
+                return Some(PathBuf::from(&arg["--config=".len()..]));
+            }
+            _ => {}
+        }
+    }
+    std::env::var_os("DYNO_CONFIG").map(PathBuf::from)
 }
 
-fn main() -> Result<()> {
+/// Replace a config file alias in subcommand position with its expansion.
+/// Built-in subcommands always take precedence over aliases.
+fn expand_alias(
+    command: &clap::Command,
+    args: Vec<OsString>,
+    config: &Config,
+) -> Result<Vec<OsString>> {
+    if config.aliases.is_empty() {
+        return Ok(args);
+    }
+    let mut i = 1;
+    while let Some(arg) = args.get(i).and_then(|arg| arg.to_str()) {
+        if !arg.starts_with('-') || arg == "--" {
+            break;
+        }
+        if arg
+            .strip_prefix("--")
+            .is_some_and(|long| takes_value(command, long))
+        {
+            i += 1;
+        }
+        i += 1;
+    }
+    let name = match args.get(i).and_then(|arg| arg.to_str()) {
+        Some(name) if command.find_subcommand(name).is_none() => name,
+        _ => return Ok(args),
+    };
+    match config.alias_args(name) {
+        Some(expansion) => {
+            let mut expanded = args[..i].to_vec();
+            expanded.extend(expansion?.into_iter().map(OsString::from));
+            expanded.extend_from_slice(&args[i + 1..]);
+            Ok(expanded)
+        }
+        None => Ok(args),
+    }
+}
+
+/// Whether `args` run dyno doctor. Aliases can't be expanded without the
+/// config file, so this only recognizes the subcommand itself.
+fn is_doctor(command: &clap::Command, args: &[OsString]) -> bool {
+    command
+        .clone()
+        .try_get_matches_from(args)
+        .ok()
+        .and_then(|matches| Opts::from_arg_matches(&matches).ok())
+        .is_some_and(|opts| matches!(opts.cmd, Command::Doctor))
+}
+
+fn main() {
+    if let Err(err) = run() {
+        // Plugins report their own errors
+        if let Some(plugin::PluginExit(code)) = err.downcast_ref() {
+            std::process::exit(*code);
+        }
+        eprintln!("Error: {:?}", err);
+        let code = if err.is::<utils::DaemonError>() {
+            utils::EXIT_DAEMON_ERROR
+        } else {
+            1
+        };
+        std::process::exit(code);
+    }
+}
+
+fn run() -> Result<()> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    // Startup only parses arguments and reads the config file, building the
+    // clap command once. Nothing is resolved or connected before dispatch.
+    let command = Opts::command();
+    // dyno doctor reports a broken config file instead of failing on it
+    let (mut config, config_err) = match config::load(config_path(&args).as_deref()) {
+        Ok(config) => (config, None),
+        Err(err) if is_doctor(&command, &args) => (Config::default(), Some(err)),
+        Err(err) => return Err(err),
+    };
+    let args = expand_alias(&command, args, &config)?;
+    let matches = command.get_matches_from(&args);
+    let mut opts = Opts::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    if let Some(deadline) = opts.deadline {
+        deadline::init(deadline);
+    }
+    if let Command::Plugin(args) = &opts.cmd {
+        if plugin::find(&args[0]).is_none() {
+            Opts::command()
+                .error(
+                    ErrorKind::UnrecognizedSubcommand,
+                    format!(
+                        "'{}' is not a dyno subcommand, alias or plugin, \
+                         no dyno-{} found on PATH",
+                        args[0].to_string_lossy(),
+                        args[0].to_string_lossy()
+                    ),
+                )
+                .exit();
+        }
+    }
+    init_logging(opts.verbose, opts.quiet);
+    if let Some(path) = &config.path {
+        tracing::info!("Loaded config from {}", path.display());
+    }
+    tracing::debug!("args = {:?}", args);
+    redact::init(std::mem::take(&mut config.redact_keys));
+    let profile = match &opts.profile {
+        Some(name) => Some(config.select_profile(name)?),
+        None => None,
+    };
+    apply_config(&mut opts, &matches, &config, profile.as_ref())?;
+    // Other ports are only tried when none was chosen explicitly
+    let discover_port = !config.alternate_ports.is_empty()
+        && is_unset(&matches, "port")
+        && config.port.is_none()
+        && !opts.cmd.is_local();
+
     let Opts {
+        config: _,
+        profile: _,
         hostname,
         port,
+        user,
+        #[cfg(feature = "kube")]
+        kube_pod,
+        bootstrap_ssh,
+        bootstrap_cmd,
+        rpc_timeout,
+        deadline: _,
+        max_response_mb,
+        format,
+        json,
+        fields,
+        out,
+        fsync,
+        verbose: _,
+        quiet,
+        utc,
+        yes,
+        color,
         cmd,
-    } = Opts::parse();
+    } = opts;
+    progress::init(!quiet);
+    let mut format = if json { OutputFormat::Json } else { format };
+    if !fields.is_empty() {
+        // Fields are selected from the structured result
+        if !format.is_structured() {
+            format = OutputFormat::Json;
+        }
+        render::init_fields(fields);
+    }
+    style::init(color);
+    timestamp::init(utc);
+    atomic_file::init(fsync);
+    let _out_guard = match out {
+        Some(_) if !format.is_structured() => {
+            return Err(anyhow::anyhow!(
+                "--out writes structured output, please pass a --format other than text, e.g. --json"
+            ));
+        }
+        // ndjson is read line by line, every line written so far is useful
+        Some(out) => Some(render::init_out(
+            &out,
+            format == OutputFormat::Ndjson || cmd.runs_until_interrupted(),
+        )?),
+        None => None,
+    };
+
+    // A port forward already points at the port of the pod
+    #[cfg(feature = "kube")]
+    let discover_port = discover_port && kube_pod.is_none();
+    // The port forward is kept alive until the command is done
+    #[cfg(feature = "kube")]
+    let (_port_forward, hostname, port) =
+        kube::forward_target(kube_pod.filter(|_| !cmd.is_local()), hostname, port)?;
 
-    let dyno_client =
-        create_dyno_client(&hostname, port).expect("Couldn't connect to the server...");
+    // Connections are only opened by commands that talk to dynolog, local
+    // commands work offline.
+    let mut dyno_client = DynoClient::new(&hostname, port, rpc_timeout);
+    dyno_client.max_response_bytes = max_response_mb << 20;
+    // --as "" sends requests without an operator
+    dyno_client.user = user
+        .or_else(utils::login_user)
+        .filter(|user| !user.is_empty());
+    if let Some(cache_path) = config.port_cache_path().filter(|_| discover_port) {
+        let discovered =
+            discovery::discover_port(&mut dyno_client, &config.alternate_ports, &cache_path);
+        // --bootstrap-ssh starts dynolog on --port if it runs on none of them
+        if !bootstrap_ssh {
+            discovered?;
+        }
+    }
+    if bootstrap_ssh && !cmd.is_local() {
+        bootstrap::ensure_running(&dyno_client, &bootstrap_cmd, yes)?;
+    }
 
+    let ctx = Context {
+        client: dyno_client,
+        config,
+        config_err,
+        format,
+        quiet,
+        yes,
+    };
+    let result = run_command(cmd, &ctx);
+    // Failed commands keep what they printed, e.g. an unhealthy status
+    let finished = render::finish_out();
+    result.and(finished)
+}
+
+/// Settings shared by the command of an invocation, or the steps of a script
+struct Context {
+    client: DynoClient,
+    config: Config,
+    /// The config file failed to load, see dyno doctor
+    config_err: Option<anyhow::Error>,
+    format: OutputFormat,
+    quiet: bool,
+    yes: bool,
+}
+
+fn run_command(cmd: Command, ctx: &Context) -> Result<()> {
+    let Context {
+        client: dyno_client,
+        format,
+        quiet,
+        yes,
+        ..
+    } = ctx;
+    let (format, quiet, yes) = (*format, *quiet, *yes);
     match cmd {
-        Command::Status => status::run_status(dyno_client),
-        Command::Version => version::run_version(dyno_client),
+        Command::Status {
+            raw,
+            watch,
+            interval,
+        } => status::run_status(dyno_client, raw, watch.then_some(interval), format),
+        Command::Version { client_only: true } => version::run_self_version(format),
+        Command::Version { client_only: false } => version::run_version(dyno_client, format),
         Command::Gputrace {
             job_id,
             pids,
             log_file,
             duration_ms,
+            duration,
             iterations,
             profile_start_time,
+            profile_start_iteration,
             profile_start_iteration_roundup,
             process_limit,
+            preset,
             record_shapes,
             profile_memory,
             with_stacks,
             with_flops,
             with_modules,
             fail_on_no_process,
+            capture_id,
+            manifest,
         } => {
-            let trigger_config = if iterations > 0 {
-                GpuTraceTriggerConfig::IterationBased {
-                    profile_start_iteration_roundup,
-                    iterations,
-                }
-            } else {
-                GpuTraceTriggerConfig::DurationBased {
-                    profile_start_time,
-                    duration_ms,
-                }
-            };
-            let trace_options = GpuTraceOptions {
+            let request = GpuTraceFlags {
+                job_id,
+                pids,
+                log_file,
+                duration_ms: duration.map(|d| d.as_millis() as u64).or(duration_ms),
+                iterations,
+                profile_start_time,
+                profile_start_iteration,
+                profile_start_iteration_roundup,
+                process_limit: Some(process_limit),
+                preset,
                 record_shapes,
                 profile_memory,
                 with_stacks,
                 with_flops,
                 with_modules,
+                capture_id,
+            }
+            .build()?;
+            let cli_config = GpuTraceCliConfig {
+                fail_on_no_process,
+                quiet,
+                manifest,
             };
-            let trace_config = GpuTraceConfig {
-                log_file,
-                trigger_config,
-                trace_options,
-            };
-            let cli_config = GpuTraceCliConfig { fail_on_no_process };
-            gputrace::run_gputrace(
-                dyno_client,
-                job_id,
-                &pids,
-                process_limit,
-                trace_config,
-                cli_config,
-            )
-        }
-        Command::DcgmPause { duration_s } => dcgm::run_dcgm_pause(dyno_client, duration_s),
-        Command::DcgmResume => dcgm::run_dcgm_resume(dyno_client),
+            gputrace::run_gputrace(dyno_client, request, cli_config, format)
+        }
+        Command::DcgmPause {
+            duration_s,
+            duration,
+            until,
+        } => {
+            let duration_s = dcgm::pause_duration_s(duration_s, duration, until)?;
+            dcgm::run_dcgm_pause(dyno_client, duration_s, yes, format)
+        }
+        Command::DcgmResume => dcgm::run_dcgm_resume(dyno_client, format),
+        Command::Doctor => doctor::run_doctor(
+            dyno_client,
+            ctx.config.path.as_deref(),
+            ctx.config_err.as_ref(),
+            format,
+        ),
+        Command::Bench {
+            requests,
+            concurrency,
+            rpc,
+        } => bench::run_bench(dyno_client, requests, concurrency, rpc, format),
+        #[cfg(feature = "serve")]
+        Command::Serve { listen, log_dir } => serve::run_serve(dyno_client, &listen, &log_dir),
+        Command::TraceSummary { trace_file, top } => {
+            trace_summary::run_trace_summary(&trace_file, top, format)
+        }
+        Command::TraceDiff {
+            base_file,
+            new_file,
+            threshold_pct,
+        } => trace_diff::run_trace_diff(&base_file, &new_file, threshold_pct, format),
+        Command::TraceMerge {
+            output,
+            trace_files,
+        } => trace_merge::run_trace_merge(&trace_files, &output, format),
+        Command::Script { file, keep_going } => {
+            run_steps(&script::read_steps(&file)?, keep_going, ctx)
+        }
+        Command::Run { chain } => run_steps(&script::chain_steps(&chain)?, false, ctx),
+        Command::Schedule {
+            cron,
+            jitter,
+            max_runs,
+            command: words,
+        } => {
+            let command = Opts::command();
+            // Fail now rather than at the first run
+            parse_script_step(&command, &words, &ctx.config)?;
+            schedule::run_schedule(&cron, jitter, max_runs, &words.join(" "), || {
+                run_command(parse_script_step(&command, &words, &ctx.config)?, ctx)
+            })
+        }
+        Command::DynoSelf(SelfCommand::Version) => version::run_self_version(format),
+        Command::Plugin(args) => plugin::run_plugin(&args, dyno_client, format),
         // ... add new commands here
     }
 }
+
+/// Run the steps of dyno script or dyno run
+fn run_steps(steps: &[script::Step], keep_going: bool, ctx: &Context) -> Result<()> {
+    let command = Opts::command();
+    // Typos fail the script before any step ran
+    for step in steps {
+        parse_script_step(&command, &step.words, &ctx.config)
+            .with_context(|| format!("Invalid step on line {}", step.line))?;
+    }
+    script::run_script(steps, keep_going, ctx.format, |step| {
+        run_command(parse_script_step(&command, &step.words, &ctx.config)?, ctx)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Command {
+        Opts::try_parse_from(args).unwrap().cmd
+    }
+
+    #[test]
+    fn test_local_commands_stay_offline() {
+        assert!(parse(&["dyno", "--hostname", "x.invalid", "trace-summary", "t.json"]).is_local());
+        assert!(parse(&["dyno", "trace-diff", "a.json", "b.json"]).is_local());
+        assert!(parse(&["dyno", "trace-merge", "-o", "m.json", "a.json", "b.json"]).is_local());
+        assert!(parse(&["dyno", "version", "--client-only"]).is_local());
+        assert!(parse(&["dyno", "self", "version"]).is_local());
+        assert!(!parse(&["dyno", "version"]).is_local());
+        assert!(!parse(&["dyno", "status"]).is_local());
+    }
+
+    #[test]
+    fn test_is_doctor() {
+        let command = Opts::command();
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert!(is_doctor(&command, &args(&["dyno", "doctor"])));
+        assert!(is_doctor(
+            &command,
+            &args(&["dyno", "--port", "1", "doctor"])
+        ));
+        assert!(!is_doctor(
+            &command,
+            &args(&["dyno", "gputrace", "--log-file", "doctor"])
+        ));
+        assert!(!is_doctor(&command, &args(&["dyno", "dcotor"])));
+    }
+
+    #[test]
+    fn test_pids() {
+        let pids = |args: &[&str]| match Opts::try_parse_from(args).map(|opts| opts.cmd) {
+            Ok(Command::Gputrace { pids, .. }) => Ok(pids),
+            Ok(cmd) => panic!("{:?}", cmd),
+            Err(err) => Err(err),
+        };
+        let gputrace = ["dyno", "gputrace", "--log-file", "/tmp/t.json"];
+        assert_eq!(pids(&gputrace).unwrap(), vec![0]);
+        assert_eq!(
+            pids(&[&gputrace[..], &["--pids", "12,34"]].concat()).unwrap(),
+            vec![12, 34]
+        );
+        for invalid in [
+            "",
+            "1,,2",
+            "-5",
+            "12]",
+            "1, 2",
+            "0x10",
+            "2147483648",
+            "4294967295",
+        ] {
+            assert!(
+                pids(&[&gputrace[..], &["--pids", invalid]].concat()).is_err(),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn test_bench_ranges() {
+        let bench = |args: &[&str]| {
+            Opts::try_parse_from([&["dyno", "bench"], args].concat()).map(|opts| opts.cmd)
+        };
+        match bench(&["--requests", "1", "--concurrency", "1"]) {
+            Ok(Command::Bench {
+                requests: 1,
+                concurrency: 1,
+                ..
+            }) => {}
+            other => panic!("{:?}", other),
+        }
+        assert!(bench(&["--requests", "0"]).is_err());
+        assert!(bench(&["--concurrency", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_expand_alias() {
+        let config: Config = toml::from_str(r#"aliases = { st = "status --raw" }"#).unwrap();
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        let command = Opts::command();
+        assert_eq!(
+            expand_alias(&command, args(&["dyno", "--port", "1", "st"]), &config).unwrap(),
+            args(&["dyno", "--port", "1", "status", "--raw"])
+        );
+        assert_eq!(
+            expand_alias(&command, args(&["dyno", "status"]), &config).unwrap(),
+            args(&["dyno", "status"])
+        );
+    }
+
+    #[test]
+    fn test_profile_over_env() {
+        let mut config: Config = toml::from_str(
+            r#"
+port = 1780
+[profiles.prod-eu]
+port = 1779
+"#,
+        )
+        .unwrap();
+        let profile = config.select_profile("prod-eu").unwrap();
+        let port = |args: &[&str], profile: Option<&ConnectionProfile>| {
+            let matches = Opts::command().get_matches_from(args);
+            let mut opts = Opts::from_arg_matches(&matches).unwrap();
+            apply_config(&mut opts, &matches, &config, profile).unwrap();
+            opts.port
+        };
+        assert_eq!(
+            port(&["dyno", "--profile", "prod-eu", "status"], Some(&profile)),
+            1779
+        );
+        assert_eq!(
+            port(
+                &["dyno", "--profile", "prod-eu", "--port", "1", "status"],
+                Some(&profile)
+            ),
+            1
+        );
+
+        // The environment is only outranked by values of the profile
+        assert!(config_overrides(Some(ValueSource::EnvVariable), true));
+        assert!(!config_overrides(Some(ValueSource::EnvVariable), false));
+        assert!(!config_overrides(Some(ValueSource::CommandLine), true));
+        assert!(config_overrides(Some(ValueSource::DefaultValue), false));
+        assert!(config_overrides(None, false));
+    }
+
+    #[test]
+    fn test_gputrace_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+[gputrace]
+preset = "full-python"
+with_stacks = false
+record_shapes = false
+"#,
+        )
+        .unwrap();
+        let command = Opts::command();
+        let options = |line: &str| {
+            let words = shlex::split(line).unwrap();
+            match parse_script_step(&command, &words, &config).unwrap() {
+                Command::Gputrace {
+                    preset,
+                    record_shapes,
+                    profile_memory,
+                    with_stacks,
+                    with_flops,
+                    with_modules,
+                    ..
+                } => GpuTraceFlags {
+                    preset,
+                    record_shapes,
+                    profile_memory,
+                    with_stacks,
+                    with_flops,
+                    with_modules,
+                    ..Default::default()
+                }
+                .trace_options(),
+                cmd => panic!("{:?}", cmd),
+            }
+        };
+        let gputrace = "gputrace --log-file /tmp/t.json";
+        // Config file options override the config file preset
+        let from_config = options(gputrace);
+        assert!(from_config.with_modules);
+        assert!(!from_config.with_stacks);
+        assert!(!from_config.record_shapes);
+        // An explicit preset overrides the config file
+        let from_preset = options(&format!(
+            "{} --preset memory-debug --duration-ms 500",
+            gputrace
+        ));
+        assert!(from_preset.with_stacks);
+        assert!(from_preset.record_shapes);
+        assert!(from_preset.profile_memory);
+        assert!(!from_preset.with_modules);
+        // Explicit flags override the preset
+        let from_flags = options(&format!(
+            "{} --preset memory-debug --duration-ms 500 --with-stacks=false",
+            gputrace
+        ));
+        assert!(!from_flags.with_stacks);
+        assert!(from_flags.record_shapes);
+    }
+
+    #[test]
+    fn test_parse_script_step() {
+        let config: Config = toml::from_str(
+            r#"
+aliases = { st = "status --raw" }
+gputrace = { duration_ms = 2000 }
+"#,
+        )
+        .unwrap();
+        let command = Opts::command();
+        let step = |line: &str| {
+            let words = shlex::split(line).unwrap();
+            parse_script_step(&command, &words, &config)
+        };
+        assert!(matches!(
+            step("st").unwrap(),
+            Command::Status { raw: true, .. }
+        ));
+        assert!(matches!(
+            step("gputrace --log-file /tmp/t.json").unwrap(),
+            Command::Gputrace {
+                duration_ms: Some(2000),
+                ..
+            }
+        ));
+        assert!(step("status --json").is_err());
+        assert!(step("--hostname x status").is_err());
+        assert!(step("script plan.dyno").is_err());
+        assert!(step("run status").is_err());
+        assert!(step("schedule --cron '* * * * *' status").is_err());
+        assert!(step("bogus").is_err());
+    }
+}
@@ -60,6 +60,31 @@ nlohmann::json toJson(const std::string& message) {
   return result;
 }
 
+// longer operator names sent by clients are truncated in the log
+constexpr size_t kMaxUserLength = 64;
+
+// the operator dyno sends as "user", with control characters escaped so a
+// client can't forge log lines, and truncated so it can't flood the log
+inline std::string requestUser(const nlohmann::json& request) {
+  auto it = request.find("user");
+  if (it == request.end() || !it->is_string()) {
+    return "unknown";
+  }
+  const std::string& user = it->get_ref<const std::string&>();
+  std::string result;
+  for (unsigned char c : user.substr(0, kMaxUserLength)) {
+    if (c < 0x20 || c == 0x7f) {
+      result += fmt::format("\\x{:02x}", c);
+    } else {
+      result += static_cast<char>(c);
+    }
+  }
+  if (user.size() > kMaxUserLength) {
+    result += "...";
+  }
+  return result;
+}
+
 template <class TServiceHandler>
 std::string SimpleJsonServer<TServiceHandler>::processOneImpl(
     const std::string& request_str) {
@@ -72,6 +97,12 @@ std::string SimpleJsonServer<TServiceHandler>::processOneImpl(
     return "";
   }
 
+  // dyno sends the operator as "user", log who triggered traces and pauses
+  auto logCaller = [&request]() {
+    LOG(INFO) << "RPC call = " << request["fn"]
+              << " from user = " << requestUser(request);
+  };
+
   if (request["fn"] == "getStatus") {
     int status = handler_->getStatus();
     response["status"] = status;
@@ -79,6 +110,7 @@ std::string SimpleJsonServer<TServiceHandler>::processOneImpl(
     std::string version = handler_->getVersion();
     response["version"] = version;
   } else if (request["fn"] == "setKinetOnDemandRequest") {
+    logCaller();
     if (!request.contains("config") || !request.contains("pids")) {
       response["status"] = "failed";
     } else {
@@ -106,6 +138,7 @@ std::string SimpleJsonServer<TServiceHandler>::processOneImpl(
       }
     }
   } else if (request["fn"] == "dcgmProfPause") {
+    logCaller();
     if (!request.contains("duration_s")) {
       response["status"] = "failed";
     } else {
@@ -114,6 +147,7 @@ std::string SimpleJsonServer<TServiceHandler>::processOneImpl(
       response["status"] = result;
     }
   } else if (request["fn"] == "dcgmProfResume") {
+    logCaller();
     bool result = handler_->dcgmProfResume();
     response["status"] = result;
   } else {